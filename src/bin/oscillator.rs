@@ -8,6 +8,7 @@ use std::f64::consts::PI;
 use std::io::{Write, stdout};
 use std::sync::{Arc, Mutex};
 use noise_maker::*;
+use sound::dynamics::*;
 use rand::prelude::*;
 use bindings::Windows::{
     Win32::{
@@ -101,7 +102,7 @@ impl EnvelopeADSR {
             amplitude = (time - self.trigger_off_time) / self.release_time * -self.sustain_amplitude + self.sustain_amplitude;
         }
 
-        if amplitude <= 0.0001_f64 {
+        if amplitude <= 0_f64 {
             amplitude = 0_f64;
         }
 
@@ -158,7 +159,13 @@ fn main() -> windows::Result<()> {
         output * 0.5_f64 // master volume
     };
 
-    let noise_maker = NoiseMaker::new::<i16, _>(0, 44100, 1, 8, 256, make_noise);
+    let noise_maker = NoiseMaker::new::<i16, _, _>(0, 44100, 1, 8, 256, make_noise);
+    // a single note can't get loud enough to need a compressor, only catch the peaks
+    noise_maker.set_dynamics(Dynamics {
+        compressor: None,
+        limiter: Some(Limiter::new(-0.3_f64, 0.0015_f64, 0.05_f64)),
+        ..Default::default()
+    });
 
     let mut current_key = -1_i32;
 
@@ -171,7 +178,7 @@ fn main() -> windows::Result<()> {
                     let mut envelope = envelope.lock().unwrap();
                     *frequency_output = octave_base_frequency * twelveth_root_of_2.powi(k as i32);
                     envelope.note_on(noise_maker.get_time());
-                    print!("\rNote On : {:.5}s {:.2}Hz", noise_maker.get_time(), *frequency_output);
+                    print!("\rNote On : {:.5}s {:.2}Hz GR: {:.1}dB Clipped: {}", noise_maker.get_time(), *frequency_output, noise_maker.gain_reduction(), noise_maker.clipped_samples());
                     let _ = stdout().flush();
                     current_key = k as i32;
                 }
@@ -184,7 +191,7 @@ fn main() -> windows::Result<()> {
             if current_key != -1 {
                 let mut envelope = envelope.lock().unwrap();
                 envelope.note_off(noise_maker.get_time());
-                print!("\rNote Off : {:.5}s        ", noise_maker.get_time());
+                print!("\rNote Off : {:.5}s GR: {:.1}dB Clipped: {}        ", noise_maker.get_time(), noise_maker.gain_reduction(), noise_maker.clipped_samples());
                 let _ = stdout().flush();
                 current_key = -1;
             }
@@ -4,11 +4,23 @@ mod bindings {
     windows::include_bindings!();
 }
 
-use std::f64::consts::PI;
+use std::f64::consts::FRAC_1_SQRT_2;
 use std::io::{Write, stdout};
 use std::sync::{Arc, Mutex};
 use noise_maker::*;
-use rand::prelude::*;
+use sound::chorus::*;
+use sound::dynamics::*;
+use sound::effect::*;
+use sound::equaliser::*;
+use sound::granular::{Granular, GrainParams};
+use sound::instrument::*;
+use sound::lfo::*;
+use sound::modulation::{Destination, ModMatrix};
+use sound::envelope::EnvelopeADSR;
+use sound::oscillator::OscType;
+use sound::patch::*;
+use sound::reverb::*;
+use sound::sampler::Sample;
 use bindings::Windows::{
     Win32::{
         UI::{
@@ -20,211 +32,42 @@ use bindings::Windows::{
     System::VirtualKey
 };
 
-fn w(hertz: f64) -> f64 {
-    hertz * 2_f64 * PI
-}
-
-#[allow(dead_code)]
-enum OscType {
-    SineWave,
-    SquareWave,
-    TriangleWave,
-    AnalogSawWave,
-    DigitalSawWave,
-    RandomNoise
-}
-
-fn osc(hertz: f64, time: f64, osc_type: OscType, lfo_hertz: f64, lfo_amplitude: f64) -> f64 {
-    let freq = w(hertz) * time + lfo_amplitude * hertz * (w(lfo_hertz) * time).sin();
-
-    match osc_type {
-        OscType::SineWave => freq.sin(),
-        OscType::SquareWave => if freq.sin() > 0_f64 { 1_f64 } else { -1_f64},
-        OscType::TriangleWave => freq.sin().asin() * 2_f64 / PI,
-        OscType::AnalogSawWave => (1..100).fold(0_f64, |output, n| output + ((n as f64 * freq).sin() / n as f64)) * 2_f64 / PI,
-        OscType::DigitalSawWave => (2_f64 / PI) * (hertz * PI * (time % (1_f64 / hertz)) - (PI / 2_f64)),
-        OscType::RandomNoise => 2_f64 * random::<f64>() - 1_f64
-    }
-}
-
-struct EnvelopeADSR {
-    attack_time: f64,
-    decay_time: f64,
-    release_time: f64,
-
-    sustain_amplitude: f64,
-    start_amplitude: f64,
+fn focused() -> bool {
+    unsafe { GetConsoleWindow() == GetForegroundWindow() }
 }
 
-impl Default for EnvelopeADSR {
-    fn default() -> Self {
-        Self {
-            attack_time: 0.01_f64,
+// polyphony's own harmonica, square and reedy, slower to speak than the sequencer's
+fn harmonica() -> Patch {
+    let layer = |osc_type, transpose, level, lfo_hertz, lfo_amplitude| Layer { osc_type, transpose, key: None, level, lfo_hertz, lfo_amplitude, voices: 1, detune: 0_f64, spread: 0_f64 };
+    Patch {
+        name: "Harmonica".to_string(),
+        volume: 1_f64,
+        envelope: EnvelopeADSR {
+            attack_time: 0.1_f64,
             decay_time: 0.01_f64,
-            release_time: 0.02_f64,
-
+            release_time: 0.2_f64,
             sustain_amplitude: 0.8_f64,
-            start_amplitude: 1_f64
-        }
-    }
-}
-
-impl EnvelopeADSR {
-    fn amplitude(&self, time: f64, time_on: f64, time_off: f64) -> f64 {
-        let mut amplitude = 0_f64;
-        let mut release_amplitude = 0_f64;
-
-        if time_on > time_off { // note is on
-            let life_time = time - time_on;
-
-            // attack
-            if life_time <= self.attack_time {
-                amplitude = life_time / self.attack_time * self.start_amplitude;
-            }
-
-            // decay
-            if life_time > self.attack_time && life_time <= self.attack_time + self.decay_time {
-                amplitude = (life_time - self.attack_time) / self.decay_time * (self.sustain_amplitude - self.start_amplitude) + self.start_amplitude;
-            }
-
-            // sustain
-            if life_time > self.attack_time + self.decay_time {
-                amplitude = self.sustain_amplitude;
-            }
-        } else { // note is off
-            let life_time = time_off - time_on;
-
-            // attack
-            if life_time <= self.attack_time {
-                release_amplitude = life_time / self.attack_time * self.start_amplitude;
-            }
-
-            // decay
-            if life_time > self.attack_time && life_time <= self.attack_time + self.decay_time {
-                release_amplitude = (life_time - self.attack_time) / self.decay_time * (self.sustain_amplitude - self.start_amplitude) + self.start_amplitude;
-            }
-
-            // sustain
-            if life_time > self.attack_time + self.decay_time {
-                release_amplitude = self.sustain_amplitude;
-            }
-
-            amplitude = (time - time_off) / self.release_time * -release_amplitude + release_amplitude;
-        }
-
-        if amplitude <= 0_f64 {
-            amplitude = 0_f64;
-        }
-
-        amplitude
+            start_amplitude: 1_f64,
+            ..Default::default()
+        }.into(),
+        layers: vec![
+            layer(OscType::SquareWave, 0, 1_f64, 5_f64, 0.001_f64),
+            layer(OscType::SquareWave, 12, 0.5_f64, 0_f64, 0_f64),
+            layer(OscType::RandomNoise, 24, 0.05_f64, 0_f64, 0_f64)
+        ],
+        note_finished: NoteFinished::Envelope,
+        matrix: ModMatrix::default()
     }
 }
 
-#[allow(dead_code)]
-enum InstrumentType {
-    Harmonica,
-    Bell,
-    Bell8
-}
-
-struct Instrument {
-    instrument_type: InstrumentType,
-    volume: f64,
-    envelope: EnvelopeADSR
-}
-
-impl Instrument {
-    fn new(instrument_type: InstrumentType) -> Self {
-        match instrument_type {
-            InstrumentType::Harmonica => Self {
-                instrument_type,
-                volume: 1_f64,
-                envelope: EnvelopeADSR {
-                    attack_time: 0.1_f64,
-                    release_time: 0.2_f64,
-                    ..Default::default()
-                }
-            },
-            InstrumentType::Bell => Self {
-                instrument_type,
-                volume: 1_f64,
-                envelope: EnvelopeADSR {
-                    decay_time: 1_f64,
-                    release_time: 1_f64,
-                    sustain_amplitude: 0_f64,
-                    ..Default::default()
-                }
-            },
-            InstrumentType::Bell8 => Self {
-                instrument_type,
-                volume: 1_f64,
-                envelope: EnvelopeADSR {
-                    decay_time: 0.5_f64,
-                    release_time: 1_f64,
-                    ..Default::default()
-                }
-            }
-        }
-    }
-
-    fn sound(&self, time: f64, n: Note) -> (f64, bool) {
-        let amplitude = self.envelope.amplitude(time, n.on, n.off);
-        let note_finished = amplitude <= 0_f64;
-        
-        (
-            amplitude * 
-            match self.instrument_type {
-                InstrumentType::Harmonica =>
-                    1_f64 * osc(scale(n.id, ScaleType::Default), n.on - time, OscType::SquareWave, 5_f64, 0.001_f64) +
-                    0.5_f64 * osc(scale(n.id + 12, ScaleType::Default), n.on - time, OscType::SquareWave, 0_f64, 0_f64) +
-                    0.05_f64 * osc(scale(n.id + 24, ScaleType::Default), n.on - time, OscType::RandomNoise, 0_f64, 0_f64),
-                InstrumentType::Bell =>
-                    1_f64 * osc(scale(n.id + 12, ScaleType::Default), n.on - time, OscType::SineWave, 5_f64, 0.001_f64) +
-                    0.5_f64 * osc(scale(n.id + 24, ScaleType::Default), n.on - time, OscType::SineWave, 0_f64, 0_f64) +
-                    0.25_f64 * osc(scale(n.id + 36, ScaleType::Default), n.on - time, OscType::SineWave, 0_f64, 0_f64),
-                InstrumentType::Bell8 =>
-                    1_f64 * osc(scale(n.id, ScaleType::Default), n.on - time, OscType::SineWave, 5_f64, 0.001_f64) +
-                    0.5_f64 * osc(scale(n.id + 12, ScaleType::Default), n.on - time, OscType::SineWave, 0_f64, 0_f64) +
-                    0.25_f64 * osc(scale(n.id + 24, ScaleType::Default), n.on - time, OscType::SineWave, 0_f64, 0_f64)
-            } *
-            self.volume,
-
-            note_finished
-        )
-    }
-}
-
-#[derive(Clone, Copy)]
-struct Note {
-    id: i32,
-    on: f64,
-    off: f64,
-    active: bool
-}
-
-impl Default for Note {
-    fn default() -> Self {
-        Self {
-            id: 0,
-            on: 0_f64,
-            off: 0_f64,
-            active: false
-        }
-    }
-}
-
-enum ScaleType {
-    Default
-}
-
-fn scale(note_id: i32, scale_type: ScaleType) -> f64 {
-    match scale_type {
-        ScaleType::Default => 256_f64 * 2_f64.powf(1_f64 / 12_f64).powi(note_id)
-    }
-}
-
-fn focused() -> bool {
-    unsafe { GetConsoleWindow() == GetForegroundWindow() }
+// the longer the key is held the further the texture wanders through the source
+fn texture(sample: Sample) -> Synth<Granular> {
+    let params = GrainParams { position: 0.5_f64, size: 0.08_f64, density: 30_f64, pitch: 0_f64, spray: 0.02_f64 };
+    let wander = |hertz| Lfo { rate: LfoRate::Hertz(hertz), retrigger: true, ..Default::default() };
+    Synth::new(Granular(Arc::new(sample), params))
+        .with_lfo(wander(0.05_f64), Destination::GrainPosition, 0.4_f64)
+        .with_lfo(wander(0.13_f64), Destination::GrainSize, 0.04_f64)
+        .with_lfo(wander(0.15_f64), Destination::GrainSpray, 0.02_f64)
 }
 
 fn main() -> windows::Result<()> {
@@ -241,31 +84,69 @@ fn main() -> windows::Result<()> {
     println!("|_____|_____|_____|_____|_____|_____|_____|_____|_____|_____|");
     println!();
 
-    let notes = Arc::new(Mutex::new(Vec::<Note>::new()));
-    let voice = Instrument::new(InstrumentType::Harmonica);
+    // every note keeps the instrument it was started with
+    let notes = Arc::new(Mutex::new(Vec::<(Voice, Arc<dyn Instrument>)>::new()));
+    // a patch given on the command line is played as it is and reloaded whenever it is
+    // saved, a WAV file is played as a granular texture
+    // chords fan out from left to right as they go up the keyboard, around its middle key
+    let watcher = match std::env::args().nth(1) {
+        Some(file) if file.ends_with(".toml") => match PatchWatcher::new(&file, |patch| Synth::new(patch).with_pan_spread(0.6_f64, 67.5_f64)) {
+            Ok(watcher) => Some(watcher),
+            Err(error) => {
+                println!("Could not load {}: {}", file, error);
+                None
+            }
+        },
+        _ => None
+    };
+    let voice: Arc<dyn Instrument> = match std::env::args().nth(1) {
+        Some(file) if !file.ends_with(".toml") => match Sample::load(&file) {
+            Ok(sample) => Arc::new(texture(sample).with_pan_spread(0.6_f64, 67.5_f64)),
+            Err(error) => {
+                println!("Could not load {}: {}", file, error);
+                Arc::new(Synth::new(harmonica()).with_pan_spread(0.6_f64, 67.5_f64))
+            }
+        },
+        _ => Arc::new(Synth::new(harmonica()).with_pan_spread(0.6_f64, 67.5_f64))
+    };
+
+    // the voice is thickened by a chorus and put in a hall, then the whole mix is widened a little
+    let mut mixer = Mixer::new()
+        .with_master_insert(Width { width: 1.2_f64 })
+        // keeps DC offset and sub-sonic rumble out of the speakers
+        .with_master_insert(Equaliser::new(vec![Band::new(BandType::HighPass, 20_f64, 0_f64, FRAC_1_SQRT_2)]));
+    let channel = mixer.add_channel(Channel::new()
+        .with_insert(Chorus::new(3, 0.8_f64, 4_f64, 0.4_f64))
+        .with_insert(Reverb::new(0.8_f64, 2.5_f64, 0.25_f64).with_pre_delay(0.03_f64)));
+    // shared with the audio thread, so the effects can still be reached while playing
+    let mixer = Arc::new(Mutex::new(mixer));
 
     let make_noise = {
         let notes = notes.clone();
+        let mixer = mixer.clone();
         move |time: f64| {
             let mut notes = notes.lock().unwrap();
-
-            let mixed_output = notes.iter_mut().fold(0_f64, |mixed_output, note| {
-                let (output, note_finished) = voice.sound(time, *note);
-                if note_finished && note.off > note.on {
-                    note.active = false;
+            let mut mixer = mixer.lock().unwrap();
+
+            // finished notes are only skipped here and taken out by the main loop, so the
+            // last hold on an instrument that has been reloaded is never let go of in here
+            for (voice, instrument) in notes.iter_mut().filter(|(voice, _)| voice.note.active) {
+                let output = instrument.render(time, voice);
+                if instrument.finished(time, voice) && voice.note.off > voice.note.on {
+                    voice.note.active = false;
                 }
-                mixed_output + output
-            });
-
-            notes.retain(|&note| note.active);
+                mixer.add(channel, output);
+            }
 
-            mixed_output * 0.2_f64 // master volume
+            mixer.mix()
         }
     };
 
-    let noise_maker = NoiseMaker::new::<i16, _>(0, 44100, 1, 8, 256, make_noise);
+    let noise_maker = NoiseMaker::new::<i16, _, _>(0, 44100, 2, 8, 512, make_noise);
+    noise_maker.set_dynamics(Dynamics::mix());
 
     loop {
+        notes.lock().unwrap().retain(|(voice, _)| voice.note.active);
         if !focused() {
             continue;
         }
@@ -274,30 +155,26 @@ fn main() -> windows::Result<()> {
             let key_state = unsafe { GetAsyncKeyState(b"ZSXCFVGBNJMK\xbcL\xbe\xbf"[k] as i32) } as u16;
             let now = noise_maker.get_time();
             let mut notes = notes.lock().unwrap();
-            if let Some(note_found) = notes.iter_mut().find(|note| note.id == k as i32) {
+            if let Some((voice_found, instrument)) = notes.iter_mut().find(|(voice, _)| voice.note.id == k as i32 + 60 && voice.note.active) {
                 if key_state & 0x8000 != 0 { // key still held
-                    if note_found.off > note_found.on { // key pressed again during release phase
-                        note_found.on = now;
-                        note_found.active = true;
+                    if voice_found.note.off > voice_found.note.on { // key pressed again during release phase
+                        instrument.retrigger(voice_found, now);
                     }
                 } else { // key released => switch it off
-                    if note_found.off < note_found.on {
-                        note_found.off = now
-                    }
+                    instrument.note_off(voice_found, now);
                 }
             } else {
                 if key_state & 0x8000 != 0 { // key pressed => create new note
-                    let note = Note {
-                        id: k as i32,
-                        on: now,
-                        active: true,
-                        ..Default::default()
-                    };
-                    notes.push(note);
+                    let instrument = watcher.as_ref().map_or_else(|| voice.clone(), PatchWatcher::instrument);
+                    notes.push((instrument.note_on(k as i32 + 60, now), instrument));
                 }
             }
         }
-        print!("\rNotes: {}", notes.lock().unwrap().len());
+        // a patch that was saved with a mistake in it keeps the last good one playing
+        for error in watcher.iter().flat_map(PatchWatcher::take_errors) {
+            println!("\rCould not reload {}", error);
+        }
+        print!("\rNotes: {} GR: {:.1}dB Clipped: {} ", notes.lock().unwrap().len(), noise_maker.gain_reduction(), noise_maker.clipped_samples());
         let _ = stdout().flush();
 
         if unsafe { GetAsyncKeyState(VirtualKey::Escape.0) } as u16 & 0x8000 != 0 {
@@ -306,6 +183,9 @@ fn main() -> windows::Result<()> {
     }
 
     noise_maker.stop();
+    if let Some(watcher) = watcher {
+        watcher.stop();
+    }
 
     Ok(())
 }
@@ -4,12 +4,21 @@ mod bindings {
     windows::include_bindings!();
 }
 
-use std::f64::consts::PI;
-use std::io::{Write, stdout};
+use std::f64::consts::FRAC_1_SQRT_2;
+use std::io::{ErrorKind, Write, stdout};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use noise_maker::*;
-use rand::prelude::*;
+use sound::chorus::*;
+use sound::delay::*;
+use sound::distortion::*;
+use sound::dynamics::*;
+use sound::effect::*;
+use sound::equaliser::*;
+use sound::instrument::*;
+use sound::patch::PatchWatcher;
+use sound::reverb::*;
+use sound::sampler::*;
 use bindings::Windows::{
     Win32::{
         UI::{
@@ -21,269 +30,24 @@ use bindings::Windows::{
     System::VirtualKey
 };
 
-fn w(hertz: f64) -> f64 {
-    hertz * 2_f64 * PI
-}
-
-#[allow(dead_code)]
-enum OscType {
-    SineWave,
-    SquareWave,
-    TriangleWave,
-    AnalogSawWave,
-    DigitalSawWave,
-    RandomNoise
-}
-
-fn osc(hertz: f64, time: f64, osc_type: OscType, lfo_hertz: f64, lfo_amplitude: f64) -> f64 {
-    let freq = w(hertz) * time + lfo_amplitude * hertz * (w(lfo_hertz) * time).sin();
-
-    match osc_type {
-        OscType::SineWave => freq.sin(),
-        OscType::SquareWave => if freq.sin() > 0_f64 { 1_f64 } else { -1_f64},
-        OscType::TriangleWave => freq.sin().asin() * 2_f64 / PI,
-        OscType::AnalogSawWave => (1..100).fold(0_f64, |output, n| output + ((n as f64 * freq).sin() / n as f64)) * 2_f64 / PI,
-        OscType::DigitalSawWave => (2_f64 / PI) * (hertz * PI * (time % (1_f64 / hertz)) - (PI / 2_f64)),
-        OscType::RandomNoise => 2_f64 * random::<f64>() - 1_f64
-    }
-}
-
-struct EnvelopeADSR {
-    attack_time: f64,
-    decay_time: f64,
-    release_time: f64,
-
-    sustain_amplitude: f64,
-    start_amplitude: f64,
-}
-
-impl Default for EnvelopeADSR {
-    fn default() -> Self {
-        Self {
-            attack_time: 0.1_f64,
-            decay_time: 0.1_f64,
-            release_time: 0.2_f64,
-
-            sustain_amplitude: 1_f64,
-            start_amplitude: 1_f64
-        }
-    }
+fn focused() -> bool {
+    unsafe { GetConsoleWindow() == GetForegroundWindow() }
 }
 
-impl EnvelopeADSR {
-    fn amplitude(&self, time: f64, time_on: f64, time_off: f64) -> f64 {
-        let mut amplitude = 0_f64;
-        let mut release_amplitude = 0_f64;
-
-        if time_on > time_off { // note is on
-            let life_time = time - time_on;
-
-            // attack
-            if life_time <= self.attack_time {
-                amplitude = life_time / self.attack_time * self.start_amplitude;
+// recorded drum if there is a sample for it, synthesised one otherwise, placed
+// where it sits in the kit
+fn drum(file: &str, fallback: impl Sound + 'static, pan: f64) -> Arc<dyn Instrument> {
+    match Sampler::from_file(file, 64, PlayMode::OneShot) {
+        Ok(sampler) => Arc::new(Synth::new(sampler).with_pan(pan)),
+        Err(error) => {
+            if error.kind() != ErrorKind::NotFound {
+                println!("Could not load {}: {}", file, error);
             }
-
-            // decay
-            if life_time > self.attack_time && life_time <= self.attack_time + self.decay_time {
-                amplitude = (life_time - self.attack_time) / self.decay_time * (self.sustain_amplitude - self.start_amplitude) + self.start_amplitude;
-            }
-
-            // sustain
-            if life_time > self.attack_time + self.decay_time {
-                amplitude = self.sustain_amplitude;
-            }
-        } else { // note is off
-            let life_time = time_off - time_on;
-
-            // attack
-            if life_time <= self.attack_time {
-                release_amplitude = life_time / self.attack_time * self.start_amplitude;
-            }
-
-            // decay
-            if life_time > self.attack_time && life_time <= self.attack_time + self.decay_time {
-                release_amplitude = (life_time - self.attack_time) / self.decay_time * (self.sustain_amplitude - self.start_amplitude) + self.start_amplitude;
-            }
-
-            // sustain
-            if life_time > self.attack_time + self.decay_time {
-                release_amplitude = self.sustain_amplitude;
-            }
-
-            amplitude = (time - time_off) / self.release_time * -release_amplitude + release_amplitude;
-        }
-
-        if amplitude <= 0.01_f64 {
-            amplitude = 0_f64;
+            Arc::new(Synth::new(fallback).with_pan(pan))
         }
-
-        amplitude
     }
 }
 
-#[allow(dead_code)]
-#[derive(PartialEq)]
-enum InstrumentType {
-    Harmonica,
-    Bell,
-    Bell8,
-    DrumKick,
-    DrumSnare,
-    DrumHiHat
-}
-
-struct Instrument {
-    instrument_type: InstrumentType,
-    volume: f64,
-    envelope: EnvelopeADSR
-}
-
-impl Instrument {
-    fn new(instrument_type: InstrumentType) -> Self {
-        match instrument_type {
-            InstrumentType::Harmonica => Self {
-                instrument_type,
-                volume: 0.3_f64,
-                envelope: EnvelopeADSR {
-                    attack_time: 0_f64,
-                    decay_time: 1_f64,
-                    release_time: 0.1_f64,
-                    sustain_amplitude: 0.95_f64,
-                    ..Default::default()
-                }
-            },
-            InstrumentType::Bell => Self {
-                instrument_type,
-                volume: 1_f64,
-                envelope: EnvelopeADSR {
-                    attack_time: 0.01_f64,
-                    decay_time: 1_f64,
-                    release_time: 1_f64,
-                    sustain_amplitude: 0_f64,
-                    ..Default::default()
-                }
-            },
-            InstrumentType::Bell8 => Self {
-                instrument_type,
-                volume: 1_f64,
-                envelope: EnvelopeADSR {
-                    attack_time: 0.01_f64,
-                    decay_time: 0.5_f64,
-                    release_time: 1_f64,
-                    sustain_amplitude: 0.8_f64,
-                    ..Default::default()
-                }
-            },
-            InstrumentType::DrumKick => Self {
-                instrument_type,
-                volume: 1_f64,
-                envelope: EnvelopeADSR {
-                    attack_time: 0.01_f64,
-                    decay_time: 0.15_f64,
-                    release_time: 0_f64,
-                    sustain_amplitude: 0_f64,
-                    ..Default::default()
-                }
-            },
-            InstrumentType::DrumSnare => Self {
-                instrument_type,
-                volume: 1_f64,
-                envelope: EnvelopeADSR {
-                    attack_time: 0_f64,
-                    decay_time: 0.2_f64,
-                    release_time: 0_f64,
-                    sustain_amplitude: 0_f64,
-                    ..Default::default()
-                }
-            },
-            InstrumentType::DrumHiHat => Self {
-                instrument_type,
-                volume: 0.5_f64,
-                envelope: EnvelopeADSR {
-                    attack_time: 0.01_f64,
-                    decay_time: 0.05_f64,
-                    release_time: 0_f64,
-                    sustain_amplitude: 0_f64,
-                    ..Default::default()
-                }
-            }
-        }
-    }
-
-    fn sound(&self, time: f64, n: Note) -> (f64, bool) {
-        let amplitude = self.envelope.amplitude(time, n.on, n.off);
-        let note_finished = match self.instrument_type {
-            InstrumentType::Harmonica | InstrumentType::Bell | InstrumentType::Bell8 => amplitude <= 0_f64,
-            InstrumentType::DrumKick => time - n.on >= 1.5_f64,
-            InstrumentType::DrumSnare | InstrumentType::DrumHiHat => time - n.on >= 1_f64
-        };
-        
-        (
-            amplitude * 
-            match self.instrument_type {
-                InstrumentType::Harmonica =>
-                    1_f64 * osc(scale(n.id - 12, ScaleType::Default), n.on - time, OscType::AnalogSawWave, 5_f64, 0.001_f64) +
-                    1_f64 * osc(scale(n.id, ScaleType::Default), time - n.on, OscType::SquareWave, 5_f64, 0.001_f64) +
-                    0.5_f64 * osc(scale(n.id + 12, ScaleType::Default), time - n.on, OscType::SquareWave, 0_f64, 0_f64) +
-                    0.05_f64 * osc(scale(n.id + 24, ScaleType::Default), time - n.on, OscType::RandomNoise, 0_f64, 0_f64),
-                InstrumentType::Bell =>
-                    1_f64 * osc(scale(n.id + 12, ScaleType::Default), time - n.on, OscType::SineWave, 5_f64, 0.001_f64) +
-                    0.5_f64 * osc(scale(n.id + 24, ScaleType::Default), time - n.on, OscType::SineWave, 0_f64, 0_f64) +
-                    0.25_f64 * osc(scale(n.id + 36, ScaleType::Default), time - n.on, OscType::SineWave, 0_f64, 0_f64),
-                InstrumentType::Bell8 =>
-                    1_f64 * osc(scale(n.id, ScaleType::Default), time - n.on, OscType::SquareWave, 5_f64, 0.001_f64) +
-                    0.5_f64 * osc(scale(n.id + 12, ScaleType::Default), time - n.on, OscType::SineWave, 0_f64, 0_f64) +
-                    0.25_f64 * osc(scale(n.id + 24, ScaleType::Default), time - n.on, OscType::SineWave, 0_f64, 0_f64),
-                InstrumentType::DrumKick =>
-                    0.99_f64 * osc(scale(28, ScaleType::Default), time - n.on, OscType::SineWave, 1_f64, 1_f64) +
-                    0.01_f64 * osc(0_f64, time - n.on, OscType::RandomNoise, 0_f64, 0_f64),
-                InstrumentType::DrumSnare =>
-                    0.5_f64 * osc(scale(n.id - 24, ScaleType::Default), time - n.on, OscType::SineWave, 0.5_f64, 1_f64) +
-                    0.5_f64 * osc(0_f64, time - n.on, OscType::RandomNoise, 0_f64, 0_f64),
-                InstrumentType::DrumHiHat =>
-                    0.1_f64 * osc(scale(n.id - 12, ScaleType::Default), time - n.on, OscType::SquareWave, 1.5_f64, 1_f64) +
-                    0.9_f64 * osc(0_f64, time - n.on, OscType::RandomNoise, 0_f64, 0_f64)
-            } *
-            self.volume,
-
-            note_finished
-        )
-    }
-}
-
-#[derive(Clone, Copy)]
-struct Note {
-    id: i32,
-    on: f64,
-    off: f64,
-    active: bool
-}
-
-impl Default for Note {
-    fn default() -> Self {
-        Self {
-            id: 0,
-            on: 0_f64,
-            off: 0_f64,
-            active: false
-        }
-    }
-}
-
-enum ScaleType {
-    Default
-}
-
-fn scale(note_id: i32, scale_type: ScaleType) -> f64 {
-    match scale_type {
-        ScaleType::Default => 8_f64 * 2_f64.powf(1_f64 / 12_f64).powi(note_id)
-    }
-}
-
-fn focused() -> bool {
-    unsafe { GetConsoleWindow() == GetForegroundWindow() }
-}
-
 fn main() -> windows::Result<()> {
     for (id, name) in enumerate().iter() {
         println!("Found Output Device: {} - {}", id, name);
@@ -298,39 +62,79 @@ fn main() -> windows::Result<()> {
     println!("|_____|_____|_____|_____|_____|_____|_____|_____|_____|_____|");
     println!();
 
-    let notes = Arc::new(Mutex::new(Vec::<(Note, Arc<Instrument>)>::new()));
-    let harmonica = Arc::new(Instrument::new(InstrumentType::Harmonica));
+    let beats = 4;
+    let sub_beats = 4;
+    let tempo = 90_f64;
+
+    let notes = Arc::new(Mutex::new(Vec::<(Voice, Arc<dyn Instrument>, usize)>::new()));
+    // synced LFOs in the instrument on the keys follow the drums
+    let harmonica: Arc<dyn Instrument> = Arc::new(Synth::new(Harmonica).with_tempo(tempo).with_pan_spread(0.5_f64, 71.5_f64));
+    // a patch given on the command line is played on the keys instead of the harmonica,
+    // and reloaded whenever it is saved
+    let watcher = std::env::args().nth(1).and_then(|file| {
+        match PatchWatcher::new(&file, move |patch| Synth::new(patch).with_tempo(tempo).with_pan_spread(0.5_f64, 71.5_f64)) {
+            Ok(watcher) => Some(watcher),
+            Err(error) => {
+                println!("Could not load {}: {}", file, error);
+                None
+            }
+        }
+    });
+
+    let mut mixer = Mixer::new()
+        // keeps DC offset and sub-sonic rumble out of the speakers
+        .with_master_insert(Equaliser::new(vec![Band::new(BandType::HighPass, 20_f64, 0_f64, FRAC_1_SQRT_2)]));
+    // a room everything feeds a little of, the harmonica more than the drums
+    let room = mixer.add_bus(Bus::new().with_insert(Reverb::new(0.6_f64, 1.8_f64, 1_f64).with_pre_delay(0.02_f64)));
+    // the harmonica is thickened by a chorus, then echoes three sub beats later, bouncing from side to side
+    let harmonica_channel = mixer.add_channel(Channel::new()
+        .with_insert(Chorus::new(3, 0.8_f64, 4_f64, 0.4_f64))
+        .with_insert(Delay::new(DelayTime::Synced { tempo, beats: 3_f64 / sub_beats as f64 }, 0.4_f64, 0.3_f64).with_ping_pong(true))
+        .with_send(room, 0.3_f64));
+    // the drums lose the low mid mud and the rumble under the kick, then get a touch of tube warmth
+    let drum_channel = mixer.add_channel(Channel::new()
+        .with_insert(Equaliser::new(vec![
+            Band::new(BandType::HighPass, 35_f64, 0_f64, FRAC_1_SQRT_2),
+            Band::new(BandType::Bell, 300_f64, -4_f64, 1.2_f64)
+        ]))
+        .with_insert(Distortion::new(Shape::Tube, 2_f64, Oversampling::X2).with_level(0.6_f64))
+        .with_send(room, 0.1_f64));
+    // shared with the audio thread, so the effects can still be reached while playing
+    let mixer = Arc::new(Mutex::new(mixer));
 
     let make_noise = {
         let notes = notes.clone();
+        let mixer = mixer.clone();
         move |time: f64| {
             let mut notes = notes.lock().unwrap();
-
-            let mixed_output = notes.iter_mut().fold(0_f64, |mixed_output, (note, voice)| {
-                let (output, note_finished) = voice.sound(time, *note);
-                if note_finished {
-                    note.active = false;
+            let mut mixer = mixer.lock().unwrap();
+
+            // finished notes are only skipped here and taken out by the main loop, so the
+            // last hold on an instrument that has been reloaded is never let go of in here
+            for (voice, instrument, channel) in notes.iter_mut().filter(|(voice, _, _)| voice.note.active) {
+                let output = instrument.render(time, voice);
+                // a key that is still held keeps its note, as in polyphony, so it isn't struck
+                // again when the sound dies away ; the drums are never let go of
+                let released = *channel != harmonica_channel || voice.note.off > voice.note.on;
+                if instrument.finished(time, voice) && released {
+                    voice.note.active = false;
                 }
-                mixed_output + output
-            });
-
-            notes.retain(|(note, _)| note.active);
+                mixer.add(*channel, output);
+            }
 
-            mixed_output * 0.2_f64 // master volume
+            mixer.mix()
         }
     };
 
-    let noise_maker = NoiseMaker::new::<i16, _>(0, 44100, 1, 8, 256, make_noise);
+    let noise_maker = NoiseMaker::new::<i16, _, _>(0, 44100, 2, 8, 512, make_noise);
+    noise_maker.set_dynamics(Dynamics::mix());
 
     let drum_beats = vec![
-        ("X...X...X..X.X..", Arc::new(Instrument::new(InstrumentType::DrumKick))),
-        ("..X...X...X...X.", Arc::new(Instrument::new(InstrumentType::DrumSnare))),
-        ("X.X.X.X.X.X.X.XX", Arc::new(Instrument::new(InstrumentType::DrumHiHat))),
+        ("X...X...X..X.X..", drum("samples/kick.wav", DrumKick, 0_f64)),
+        ("..X...X...X...X.", drum("samples/snare.wav", DrumSnare, -0.15_f64)),
+        ("X.X.X.X.X.X.X.XX", drum("samples/hihat.wav", DrumHiHat, 0.35_f64)),
     ];
 
-    let beats = 4;
-    let sub_beats = 4;
-    let tempo = 90_f64;
     let beat_time = 60_f64 / tempo / sub_beats as f64;
     let mut current_beat = 0;
     let total_beats = beats * sub_beats;
@@ -346,6 +150,7 @@ fn main() -> windows::Result<()> {
         tp1 = tp2;
         wall_time += elapsed_time;
         let now = noise_maker.get_time();
+        notes.lock().unwrap().retain(|(voice, _, _)| voice.note.active);
 
         accumulate += elapsed_time;
         while accumulate >= beat_time {
@@ -356,16 +161,10 @@ fn main() -> windows::Result<()> {
                 current_beat = 0;
             }
 
-            drum_beats.iter().for_each(|(beat, voice)| {
+            drum_beats.iter().for_each(|(beat, instrument)| {
                 let mut notes = notes.lock().unwrap();
                 if beat.chars().nth(current_beat) == Some('X') {
-                    let note = Note {
-                        id: 64,
-                        on: now,
-                        active: true,
-                        ..Default::default()
-                    };
-                    notes.push((note, voice.clone()));
+                    notes.push((instrument.note_on(64, now), instrument.clone(), drum_channel));
                 }
             });
         }
@@ -374,30 +173,26 @@ fn main() -> windows::Result<()> {
             for k in 0..16 {
                 let key_state = unsafe { GetAsyncKeyState(b"ZSXCFVGBNJMK\xbcL\xbe\xbf"[k] as i32) } as u16;
                 let mut notes = notes.lock().unwrap();
-                if let Some((note_found, _)) = notes.iter_mut().find(|(note, voice)| note.id == k as i32 + 64 && Arc::ptr_eq(voice, &harmonica)) {
+                if let Some((voice_found, instrument, _)) = notes.iter_mut().find(|(voice, _, channel)| voice.note.id == k as i32 + 64 && *channel == harmonica_channel && voice.note.active) {
                     if key_state & 0x8000 != 0 { // key still held
-                        if note_found.off > note_found.on { // key pressed again during release phase
-                            note_found.on = now;
-                            note_found.active = true;
+                        if voice_found.note.off > voice_found.note.on { // key pressed again during release phase
+                            instrument.retrigger(voice_found, now);
                         }
                     } else { // key released => switch it off
-                        if note_found.off < note_found.on {
-                            note_found.off = now
-                        }
+                        instrument.note_off(voice_found, now);
                     }
                 } else {
                     if key_state & 0x8000 != 0 { // key pressed => create new note
-                        let note = Note {
-                            id: k as i32 + 64,
-                            on: now,
-                            active: true,
-                            ..Default::default()
-                        };
-                        notes.push((note, harmonica.clone()));
+                        let instrument = watcher.as_ref().map_or_else(|| harmonica.clone(), PatchWatcher::instrument);
+                        notes.push((instrument.note_on(k as i32 + 64, now), instrument, harmonica_channel));
                     }
                 }
             }
-            print!("\rNotes: {} Wall Time: {:.5} CPU Time: {:.5} Latency: {:.5}", notes.lock().unwrap().len(), wall_time, now, wall_time - now);
+            // a patch that was saved with a mistake in it keeps the last good one playing
+            for error in watcher.iter().flat_map(PatchWatcher::take_errors) {
+                println!("\rCould not reload {}", error);
+            }
+            print!("\rNotes: {} Wall Time: {:.5} CPU Time: {:.5} Latency: {:.5} GR: {:.1}dB Clipped: {}", notes.lock().unwrap().len(), wall_time, now, wall_time - now, noise_maker.gain_reduction(), noise_maker.clipped_samples());
             let _ = stdout().flush();
 
             if unsafe { GetAsyncKeyState(VirtualKey::Escape.0) } as u16 & 0x8000 != 0 {
@@ -407,6 +202,9 @@ fn main() -> windows::Result<()> {
     }
 
     noise_maker.stop();
+    if let Some(watcher) = watcher {
+        watcher.stop();
+    }
 
     Ok(())
 }
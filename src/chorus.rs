@@ -0,0 +1,348 @@
+use std::f64::consts::PI;
+use crate::oscillator::SAMPLE_RATE;
+use crate::effect::Effect;
+
+// a sine LFO that runs on its own clock, phase in cycles
+#[derive(Clone, Copy)]
+struct Sweep {
+    phase: f64
+}
+
+impl Sweep {
+    // between -1 and 1, offset cycles ahead of the sweep
+    fn value(&self, offset: f64) -> f64 {
+        (2_f64 * PI * (self.phase + offset)).sin()
+    }
+
+    fn advance(&mut self, rate: f64) {
+        self.phase = (self.phase + rate / SAMPLE_RATE).fract();
+    }
+}
+
+// a delay line read at moving, fractional positions
+#[derive(Clone)]
+struct ModulatedLine {
+    buffer: Vec<f64>,
+    position: usize
+}
+
+impl ModulatedLine {
+    fn new(seconds: f64) -> Self {
+        Self { buffer: vec![0_f64; (seconds * SAMPLE_RATE) as usize + 2], position: 0 }
+    }
+
+    // delay in samples, at least one
+    fn read(&self, delay: f64) -> f64 {
+        let size = self.buffer.len();
+        let delay = delay.clamp(1_f64, (size - 2) as f64);
+        let whole = delay.floor();
+        let fraction = delay - whole;
+        let a = self.buffer[(self.position + size - whole as usize) % size];
+        let b = self.buffer[(self.position + size - whole as usize - 1) % size];
+        a + (b - a) * fraction
+    }
+
+    fn write(&mut self, value: f64) {
+        self.buffer[self.position] = value;
+        self.position = (self.position + 1) % self.buffer.len();
+    }
+
+    fn clear(&mut self) {
+        self.buffer.iter_mut().for_each(|sample| *sample = 0_f64);
+    }
+}
+
+// every voice reads the delay line each sample, so a knob turned all the way up
+// stops here rather than eating the audio thread
+const MAX_VOICES: usize = 8;
+
+// several copies of the sound, each delayed by a slowly wandering amount, so
+// they drift in and out of tune with each other like a section of players
+#[derive(Clone)]
+pub struct Chorus {
+    voices: usize, // from 1 to MAX_VOICES
+    pub rate: f64, // hertz
+    pub depth: f64, // milliseconds the delay wanders either side of its centre
+    pub feedback: f64,
+    pub mix: f64,
+    delay: f64, // centre of the wandering, milliseconds
+    sweep: Sweep,
+    lines: [ModulatedLine; 2],
+    echo: (f64, f64)
+}
+
+impl Chorus {
+    pub fn new(voices: usize, rate: f64, depth: f64, mix: f64) -> Self {
+        Self {
+            voices: voices.clamp(1, MAX_VOICES),
+            rate,
+            depth,
+            feedback: 0_f64,
+            mix,
+            delay: 15_f64,
+            sweep: Sweep { phase: 0_f64 },
+            lines: [ModulatedLine::new(0.05_f64), ModulatedLine::new(0.05_f64)],
+            echo: (0_f64, 0_f64)
+        }
+    }
+
+    pub fn with_feedback(mut self, feedback: f64) -> Self {
+        self.feedback = feedback;
+        self
+    }
+
+    pub fn voices(&self) -> usize {
+        self.voices
+    }
+
+    pub fn set_voices(&mut self, voices: usize) {
+        self.voices = voices.clamp(1, MAX_VOICES);
+    }
+
+    pub fn process(&mut self, (left, right): (f64, f64)) -> (f64, f64) {
+        let feedback = self.feedback.clamp(-0.9_f64, 0.9_f64);
+        self.lines[0].write(left + feedback * self.echo.0);
+        self.lines[1].write(right + feedback * self.echo.1);
+
+        // voices spread evenly around the sweep, the right side a quarter cycle behind the left
+        let depth = self.depth.clamp(0_f64, self.delay - 1_f64);
+        let mut wet = [0_f64; 2];
+        for (side, output) in wet.iter_mut().enumerate() {
+            for voice in 0..self.voices {
+                let offset = voice as f64 / self.voices as f64 + side as f64 * 0.25_f64;
+                let delay = (self.delay + depth * self.sweep.value(offset)) / 1000_f64 * SAMPLE_RATE;
+                *output += self.lines[side].read(delay);
+            }
+            *output /= (self.voices as f64).sqrt();
+        }
+        self.echo = (wet[0], wet[1]);
+        self.sweep.advance(self.rate);
+
+        let mix = self.mix.clamp(0_f64, 1_f64);
+        (left * (1_f64 - mix) + wet[0] * mix, right * (1_f64 - mix) + wet[1] * mix)
+    }
+
+    pub fn reset(&mut self) {
+        self.lines.iter_mut().for_each(|line| line.clear());
+        self.echo = (0_f64, 0_f64);
+    }
+}
+
+// a single very short sweeping delay fed back on itself, the jet plane whoosh
+#[derive(Clone)]
+pub struct Flanger {
+    pub rate: f64, // hertz
+    pub depth: f64, // milliseconds the delay sweeps above its minimum
+    pub feedback: f64, // from -1 to 1, negative gives a hollower sound
+    pub mix: f64,
+    delay: f64, // shortest delay, milliseconds
+    sweep: Sweep,
+    lines: [ModulatedLine; 2],
+    echo: (f64, f64)
+}
+
+impl Flanger {
+    pub fn new(rate: f64, depth: f64, feedback: f64, mix: f64) -> Self {
+        Self {
+            rate,
+            depth,
+            feedback,
+            mix,
+            delay: 0.5_f64,
+            sweep: Sweep { phase: 0_f64 },
+            lines: [ModulatedLine::new(0.02_f64), ModulatedLine::new(0.02_f64)],
+            echo: (0_f64, 0_f64)
+        }
+    }
+
+    pub fn process(&mut self, (left, right): (f64, f64)) -> (f64, f64) {
+        let feedback = self.feedback.clamp(-0.95_f64, 0.95_f64);
+        self.lines[0].write(left + feedback * self.echo.0);
+        self.lines[1].write(right + feedback * self.echo.1);
+
+        let depth = self.depth.clamp(0_f64, 15_f64);
+        let mut wet = [0_f64; 2];
+        for (side, output) in wet.iter_mut().enumerate() {
+            // the sweep goes from the shortest delay up to depth above it
+            let sweep = 0.5_f64 + 0.5_f64 * self.sweep.value(side as f64 * 0.25_f64);
+            *output = self.lines[side].read((self.delay + depth * sweep) / 1000_f64 * SAMPLE_RATE);
+        }
+        self.echo = (wet[0], wet[1]);
+        self.sweep.advance(self.rate);
+
+        let mix = self.mix.clamp(0_f64, 1_f64);
+        (left * (1_f64 - mix) + wet[0] * mix, right * (1_f64 - mix) + wet[1] * mix)
+    }
+
+    pub fn reset(&mut self) {
+        self.lines.iter_mut().for_each(|line| line.clear());
+        self.echo = (0_f64, 0_f64);
+    }
+}
+
+// first order all-pass, shifts the phase of everything around its corner frequency
+#[derive(Clone, Copy, Default)]
+struct AllpassStage {
+    x1: f64,
+    y1: f64
+}
+
+impl AllpassStage {
+    fn process(&mut self, coefficient: f64, input: f64) -> f64 {
+        let output = coefficient * input + self.x1 - coefficient * self.y1;
+        self.x1 = input;
+        self.y1 = output;
+        output
+    }
+}
+
+// a chain of all-passes swept up and down, mixed back with the dry sound to
+// carve moving notches
+#[derive(Clone)]
+pub struct Phaser {
+    pub rate: f64, // hertz
+    pub depth: f64, // from 0 to 1, how much of the frequency range the notches sweep
+    pub feedback: f64,
+    pub mix: f64,
+    minimum: f64, // lowest and highest corner frequency of the sweep
+    maximum: f64,
+    sweep: Sweep,
+    stages: [Vec<AllpassStage>; 2],
+    echo: (f64, f64)
+}
+
+impl Phaser {
+    // two notches for every four stages
+    pub fn new(stages: usize, rate: f64, depth: f64, feedback: f64, mix: f64) -> Self {
+        let stages = stages.max(1);
+        Self {
+            rate,
+            depth,
+            feedback,
+            mix,
+            minimum: 200_f64,
+            maximum: 4000_f64,
+            sweep: Sweep { phase: 0_f64 },
+            stages: [vec![AllpassStage::default(); stages], vec![AllpassStage::default(); stages]],
+            echo: (0_f64, 0_f64)
+        }
+    }
+
+    pub fn process(&mut self, (left, right): (f64, f64)) -> (f64, f64) {
+        let feedback = self.feedback.clamp(-0.95_f64, 0.95_f64);
+        let depth = self.depth.clamp(0_f64, 1_f64);
+        let inputs = [left + feedback * self.echo.0, right + feedback * self.echo.1];
+
+        let mut wet = [0_f64; 2];
+        for (side, output) in wet.iter_mut().enumerate() {
+            // sweeps evenly in pitch rather than in hertz
+            let sweep = 0.5_f64 + 0.5_f64 * self.sweep.value(side as f64 * 0.25_f64);
+            let hertz = self.minimum * (self.maximum / self.minimum).powf(depth * sweep);
+            let t = (PI * hertz / SAMPLE_RATE).tan();
+            let coefficient = (t - 1_f64) / (t + 1_f64);
+            *output = self.stages[side].iter_mut().fold(inputs[side], |signal, stage| stage.process(coefficient, signal));
+        }
+        self.echo = (wet[0], wet[1]);
+        self.sweep.advance(self.rate);
+
+        let mix = self.mix.clamp(0_f64, 1_f64);
+        (left * (1_f64 - mix) + wet[0] * mix, right * (1_f64 - mix) + wet[1] * mix)
+    }
+
+    pub fn reset(&mut self) {
+        self.stages.iter_mut().flatten().for_each(|stage| *stage = AllpassStage::default());
+        self.echo = (0_f64, 0_f64);
+    }
+}
+
+impl Effect for Chorus {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        Chorus::process(self, frame)
+    }
+
+    fn reset(&mut self) {
+        Chorus::reset(self)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            ("voices".to_string(), self.voices as f64),
+            ("rate".to_string(), self.rate),
+            ("depth".to_string(), self.depth),
+            ("feedback".to_string(), self.feedback),
+            ("mix".to_string(), self.mix)
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "voices" => self.set_voices(value.round().max(1_f64) as usize),
+            "rate" => self.rate = value,
+            "depth" => self.depth = value,
+            "feedback" => self.feedback = value,
+            "mix" => self.mix = value,
+            _ => return false
+        }
+        true
+    }
+}
+
+impl Effect for Flanger {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        Flanger::process(self, frame)
+    }
+
+    fn reset(&mut self) {
+        Flanger::reset(self)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            ("rate".to_string(), self.rate),
+            ("depth".to_string(), self.depth),
+            ("feedback".to_string(), self.feedback),
+            ("mix".to_string(), self.mix)
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "rate" => self.rate = value,
+            "depth" => self.depth = value,
+            "feedback" => self.feedback = value,
+            "mix" => self.mix = value,
+            _ => return false
+        }
+        true
+    }
+}
+
+impl Effect for Phaser {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        Phaser::process(self, frame)
+    }
+
+    fn reset(&mut self) {
+        Phaser::reset(self)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            ("rate".to_string(), self.rate),
+            ("depth".to_string(), self.depth),
+            ("feedback".to_string(), self.feedback),
+            ("mix".to_string(), self.mix)
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "rate" => self.rate = value,
+            "depth" => self.depth = value,
+            "feedback" => self.feedback = value,
+            "mix" => self.mix = value,
+            _ => return false
+        }
+        true
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use crate::oscillator::SAMPLE_RATE;
+use crate::effect::Effect;
+
+// longest echo a delay can hold
+pub const MAX_DELAY: f64 = 4_f64;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum DelayTime {
+    Milliseconds(f64),
+    Synced { tempo: f64, beats: f64 } // so many beats at tempo beats per minute, 0.75 is a dotted eighth
+}
+
+impl DelayTime {
+    pub fn seconds(&self) -> f64 {
+        match *self {
+            DelayTime::Milliseconds(milliseconds) => milliseconds / 1000_f64,
+            DelayTime::Synced { tempo, beats } => 60_f64 / tempo * beats
+        }
+    }
+}
+
+// stereo echo ; the feedback goes through a low pass so every repeat is darker
+// than the one before, and in ping pong mode the repeats bounce between the sides
+#[derive(Clone)]
+pub struct Delay {
+    time: DelayTime,
+    pub feedback: f64, // from 0 to just under 1
+    pub mix: f64, // 0 is all dry, 1 all wet
+    pub damping: f64, // 0 leaves the repeats alone, 1 smothers them
+    pub ping_pong: bool,
+    length: f64, // in samples
+    left: Vec<f64>,
+    right: Vec<f64>,
+    position: usize,
+    damped: (f64, f64)
+}
+
+impl Delay {
+    pub fn new(time: DelayTime, feedback: f64, mix: f64) -> Self {
+        let size = (MAX_DELAY * SAMPLE_RATE) as usize + 2;
+        let mut delay = Self {
+            time,
+            feedback,
+            mix,
+            damping: 0.3_f64,
+            ping_pong: false,
+            length: 0_f64,
+            left: vec![0_f64; size],
+            right: vec![0_f64; size],
+            position: 0,
+            damped: (0_f64, 0_f64)
+        };
+        delay.set_time(time);
+        delay
+    }
+
+    pub fn with_damping(mut self, damping: f64) -> Self {
+        self.damping = damping;
+        self
+    }
+
+    pub fn with_ping_pong(mut self, ping_pong: bool) -> Self {
+        self.ping_pong = ping_pong;
+        self
+    }
+
+    pub fn time(&self) -> DelayTime {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: DelayTime) {
+        self.time = time;
+        self.length = (time.seconds() * SAMPLE_RATE).clamp(1_f64, MAX_DELAY * SAMPLE_RATE);
+    }
+
+    // linearly interpolated, so delay times don't have to be whole samples
+    fn read(line: &[f64], position: usize, length: f64) -> f64 {
+        let size = line.len();
+        let whole = length.floor();
+        let fraction = length - whole;
+        let a = line[(position + size - whole as usize) % size];
+        let b = line[(position + size - whole as usize - 1) % size];
+        a + (b - a) * fraction
+    }
+
+    pub fn process(&mut self, (left, right): (f64, f64)) -> (f64, f64) {
+        let echo_left = Self::read(&self.left, self.position, self.length);
+        let echo_right = Self::read(&self.right, self.position, self.length);
+
+        let smoothing = self.damping.clamp(0_f64, 0.99_f64);
+        self.damped.0 += (echo_left - self.damped.0) * (1_f64 - smoothing);
+        self.damped.1 += (echo_right - self.damped.1) * (1_f64 - smoothing);
+
+        let feedback = self.feedback.clamp(0_f64, 0.99_f64);
+        let (into_left, into_right) = if self.ping_pong {
+            // everything starts on the left and each repeat crosses over
+            ((left + right) * 0.5_f64 + feedback * self.damped.1, feedback * self.damped.0)
+        } else {
+            (left + feedback * self.damped.0, right + feedback * self.damped.1)
+        };
+        self.left[self.position] = into_left;
+        self.right[self.position] = into_right;
+        self.position = (self.position + 1) % self.left.len();
+
+        let mix = self.mix.clamp(0_f64, 1_f64);
+        (
+            left * (1_f64 - mix) + echo_left * mix,
+            right * (1_f64 - mix) + echo_right * mix
+        )
+    }
+
+    // forgets everything it has heard, ready for a new sound
+    pub fn reset(&mut self) {
+        self.left.iter_mut().for_each(|sample| *sample = 0_f64);
+        self.right.iter_mut().for_each(|sample| *sample = 0_f64);
+        self.damped = (0_f64, 0_f64);
+    }
+}
+
+// time is in milliseconds, ping pong is on above a half
+impl Effect for Delay {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        Delay::process(self, frame)
+    }
+
+    fn reset(&mut self) {
+        Delay::reset(self)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            ("time".to_string(), self.time.seconds() * 1000_f64),
+            ("feedback".to_string(), self.feedback),
+            ("mix".to_string(), self.mix),
+            ("damping".to_string(), self.damping),
+            ("ping pong".to_string(), if self.ping_pong { 1_f64 } else { 0_f64 })
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "time" => self.set_time(DelayTime::Milliseconds(value)),
+            "feedback" => self.feedback = value,
+            "mix" => self.mix = value,
+            "damping" => self.damping = value,
+            "ping pong" => self.ping_pong = value >= 0.5_f64,
+            _ => return false
+        }
+        true
+    }
+}
@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+use crate::filter::*;
+use crate::oscillator::SAMPLE_RATE;
+use crate::effect::Effect;
+
+// transfer curves, all of them pass 0 through 0 and stay within -1 to 1
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Shape {
+    Tanh,
+    Overdrive, // cubic soft clip, gentle until it suddenly isn't
+    Foldback, // folds back down whatever goes past the edges, very bright
+    Tube, // tanh that leans on one side, adding even harmonics
+    Curve(Vec<(f64, f64)>) // input and output points from -1 to 1, joined by straight lines
+}
+
+impl Shape {
+    pub fn apply(&self, x: f64) -> f64 {
+        match self {
+            Shape::Tanh => x.tanh(),
+            Shape::Overdrive => {
+                let x = x.clamp(-1_f64, 1_f64);
+                1.5_f64 * (x - x * x * x / 3_f64)
+            },
+            Shape::Foldback => {
+                // a triangle wave of the input, which is x itself between -1 and 1
+                let folded = (x + 1_f64).rem_euclid(4_f64);
+                if folded < 2_f64 { folded - 1_f64 } else { 3_f64 - folded }
+            },
+            Shape::Tube => {
+                // the bias pushes one side harder into saturation, taken off again afterwards
+                // and scaled so the harder side tops out at -1
+                let bias = 0.2_f64;
+                ((x + bias).tanh() - bias.tanh()) / (1_f64 + bias.tanh())
+            },
+            Shape::Curve(points) => {
+                let x = x.clamp(-1_f64, 1_f64);
+                match points.iter().position(|&(input, _)| input >= x) {
+                    Some(0) => points[0].1,
+                    Some(i) => {
+                        let (x0, y0) = points[i - 1];
+                        let (x1, y1) = points[i];
+                        if x1 > x0 { y0 + (y1 - y0) * (x - x0) / (x1 - x0) } else { y1 }
+                    },
+                    None => points.last().map_or(x, |&(_, output)| output)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Oversampling {
+    None,
+    X2,
+    X4
+}
+
+impl Oversampling {
+    fn factor(&self) -> usize {
+        match self {
+            Oversampling::None => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4
+        }
+    }
+}
+
+// runs a nonlinearity at a multiple of the sample rate, with an 8th order
+// Butterworth low pass either side to keep the new harmonics from folding back down
+#[derive(Clone)]
+struct Oversampler {
+    factor: usize,
+    up: Vec<Filter>,
+    down: Vec<Filter>
+}
+
+impl Oversampler {
+    fn new(oversampling: Oversampling) -> Self {
+        let factor = oversampling.factor();
+        // the filters think they run at the normal rate, so the cutoff is scaled down to match
+        let cutoff = 0.45_f64 * SAMPLE_RATE / factor as f64;
+        let sections = || [0.5098_f64, 0.6013_f64, 0.9000_f64, 2.5629_f64].iter()
+            .map(|&q| Filter::new(FilterType::LowPass, cutoff, q, 1))
+            .collect::<Vec<Filter>>();
+        Self { factor, up: sections(), down: sections() }
+    }
+
+    fn process(&mut self, input: f64, mut shape: impl FnMut(f64) -> f64) -> f64 {
+        if self.factor == 1 {
+            return shape(input);
+        }
+        let mut output = 0_f64;
+        for i in 0..self.factor {
+            // zero stuffing loses level in proportion to the factor
+            let stuffed = if i == 0 { input * self.factor as f64 } else { 0_f64 };
+            let upsampled = self.up.iter_mut().fold(stuffed, |signal, section| section.process(signal));
+            let shaped = shape(upsampled);
+            output = self.down.iter_mut().fold(shaped, |signal, section| section.process(signal));
+        }
+        output
+    }
+
+    fn reset(&mut self) {
+        self.up.iter_mut().chain(self.down.iter_mut()).for_each(|section| section.reset());
+    }
+}
+
+// waveshaping distortion ; drive pushes the signal into the shape, level brings it back down
+#[derive(Clone)]
+pub struct Distortion {
+    pub shape: Shape,
+    pub drive: f64,
+    pub level: f64,
+    pub mix: f64,
+    sides: [Oversampler; 2]
+}
+
+impl Distortion {
+    pub fn new(shape: Shape, drive: f64, oversampling: Oversampling) -> Self {
+        Self {
+            shape,
+            drive,
+            level: 1_f64,
+            mix: 1_f64,
+            sides: [Oversampler::new(oversampling), Oversampler::new(oversampling)]
+        }
+    }
+
+    pub fn with_level(mut self, level: f64) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn with_mix(mut self, mix: f64) -> Self {
+        self.mix = mix;
+        self
+    }
+
+    pub fn process(&mut self, (left, right): (f64, f64)) -> (f64, f64) {
+        let shape = &self.shape;
+        let drive = self.drive;
+        let mut wet = [left, right];
+        for (side, sample) in self.sides.iter_mut().zip(wet.iter_mut()) {
+            *sample = side.process(*sample, |x| shape.apply(drive * x));
+        }
+
+        let mix = self.mix.clamp(0_f64, 1_f64);
+        (
+            self.level * (left * (1_f64 - mix) + wet[0] * mix),
+            self.level * (right * (1_f64 - mix) + wet[1] * mix)
+        )
+    }
+
+    pub fn reset(&mut self) {
+        self.sides.iter_mut().for_each(|side| side.reset());
+    }
+}
+
+// fewer bits and a lower sample rate, the grit of old samplers and consoles ;
+// the aliasing is the point here so there is no oversampling
+#[derive(Clone)]
+pub struct Bitcrusher {
+    pub bits: f64, // fractional bit depths are allowed for smoother changes
+    pub rate: f64, // hertz the signal is sampled at
+    pub mix: f64,
+    phase: f64,
+    held: (f64, f64)
+}
+
+impl Bitcrusher {
+    pub fn new(bits: f64, rate: f64) -> Self {
+        Self { bits, rate, mix: 1_f64, phase: 1_f64, held: (0_f64, 0_f64) }
+    }
+
+    pub fn process(&mut self, (left, right): (f64, f64)) -> (f64, f64) {
+        // sample and hold at the lower rate
+        self.phase += self.rate.clamp(1_f64, SAMPLE_RATE) / SAMPLE_RATE;
+        if self.phase >= 1_f64 {
+            self.phase -= 1_f64;
+            let steps = 2_f64.powf(self.bits.clamp(1_f64, 24_f64) - 1_f64);
+            let quantise = |x: f64| (x * steps).round() / steps;
+            self.held = (quantise(left), quantise(right));
+        }
+
+        let mix = self.mix.clamp(0_f64, 1_f64);
+        (left * (1_f64 - mix) + self.held.0 * mix, right * (1_f64 - mix) + self.held.1 * mix)
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 1_f64;
+        self.held = (0_f64, 0_f64);
+    }
+}
+
+impl Effect for Distortion {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        Distortion::process(self, frame)
+    }
+
+    fn reset(&mut self) {
+        Distortion::reset(self)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            ("drive".to_string(), self.drive),
+            ("level".to_string(), self.level),
+            ("mix".to_string(), self.mix)
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "drive" => self.drive = value,
+            "level" => self.level = value,
+            "mix" => self.mix = value,
+            _ => return false
+        }
+        true
+    }
+}
+
+impl Effect for Bitcrusher {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        Bitcrusher::process(self, frame)
+    }
+
+    fn reset(&mut self) {
+        Bitcrusher::reset(self)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            ("bits".to_string(), self.bits),
+            ("rate".to_string(), self.rate),
+            ("mix".to_string(), self.mix)
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "bits" => self.bits = value,
+            "rate" => self.rate = value,
+            "mix" => self.mix = value,
+            _ => return false
+        }
+        true
+    }
+}
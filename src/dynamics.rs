@@ -0,0 +1,437 @@
+use crate::oscillator::SAMPLE_RATE;
+use crate::effect::Effect;
+
+fn to_db(gain: f64) -> f64 {
+    20_f64 * gain.max(1e-9_f64).log10()
+}
+
+fn from_db(db: f64) -> f64 {
+    10_f64.powf(db / 20_f64)
+}
+
+// how much of the way a one pole smoother moves each sample to settle in about seconds
+fn coefficient(seconds: f64) -> f64 {
+    if seconds > 0_f64 { 1_f64 - (-1_f64 / (seconds * SAMPLE_RATE)).exp() } else { 1_f64 }
+}
+
+// feed forward compressor, both sides are turned down together so the stereo image stays put
+#[derive(Clone)]
+pub struct Compressor {
+    pub threshold: f64, // dB
+    pub ratio: f64, // 4 lets through a quarter of what goes over the threshold
+    pub attack: f64, // seconds
+    pub release: f64, // seconds
+    pub knee: f64, // dB wide, 0 is a hard knee
+    pub makeup: f64, // dB
+    reduction: f64 // dB, positive
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Self {
+            threshold: -12_f64,
+            ratio: 4_f64,
+            attack: 0.005_f64,
+            release: 0.1_f64,
+            knee: 6_f64,
+            makeup: 0_f64,
+            reduction: 0_f64
+        }
+    }
+}
+
+impl Compressor {
+    pub fn new(threshold: f64, ratio: f64) -> Self {
+        Self { threshold, ratio, ..Default::default() }
+    }
+
+    pub fn with_makeup(mut self, makeup: f64) -> Self {
+        self.makeup = makeup;
+        self
+    }
+
+    // dB taken off a level in dB, before smoothing
+    fn static_reduction(&self, level: f64) -> f64 {
+        let over = level - self.threshold;
+        let slope = 1_f64 - 1_f64 / self.ratio.max(1_f64);
+        if 2_f64 * over <= -self.knee {
+            0_f64
+        } else if 2_f64 * over.abs() < self.knee {
+            // eases into the ratio across the knee
+            slope * (over + self.knee / 2_f64).powi(2) / (2_f64 * self.knee)
+        } else {
+            slope * over
+        }
+    }
+
+    pub fn process(&mut self, (left, right): (f64, f64)) -> (f64, f64) {
+        let target = self.static_reduction(to_db(left.abs().max(right.abs())));
+        let speed = if target > self.reduction { self.attack } else { self.release };
+        self.reduction += (target - self.reduction) * coefficient(speed);
+
+        let gain = from_db(self.makeup - self.reduction);
+        (left * gain, right * gain)
+    }
+
+    // dB it is turning the signal down by right now
+    pub fn gain_reduction(&self) -> f64 {
+        self.reduction
+    }
+
+    pub fn reset(&mut self) {
+        self.reduction = 0_f64;
+    }
+}
+
+// the highest the signal reaches between samples, estimated from a cubic through
+// the last four of them, which is what a DAC's reconstruction filter will draw
+fn true_peak(history: &[f64; 4]) -> f64 {
+    let [y0, y1, y2, y3] = *history;
+    [0.25_f64, 0.5_f64, 0.75_f64].iter().fold(y1.abs().max(y2.abs()), |peak, &t| {
+        let c1 = 0.5_f64 * (y2 - y0);
+        let c2 = y0 - 2.5_f64 * y1 + 2_f64 * y2 - 0.5_f64 * y3;
+        let c3 = 0.5_f64 * (y3 - y0) + 1.5_f64 * (y1 - y2);
+        peak.max((((c3 * t + c2) * t + c1) * t + y1).abs())
+    })
+}
+
+// brickwall limiter: the signal is delayed by the look ahead so the gain is already
+// down by the time a peak comes out, and peaks between samples are caught as well
+#[derive(Clone)]
+pub struct Limiter {
+    ceiling: f64, // dB
+    pub release: f64, // seconds
+    delay: Vec<(f64, f64)>,
+    gains: Vec<f64>, // the gain each sample in the look ahead window wants
+    held: Vec<f64>, // the lowest of those, averaged so the gain slides down smoothly
+    held_sum: f64,
+    position: usize, // samples processed, each buffer wraps it round its own length
+    history: [[f64; 4]; 2],
+    gain: f64
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new(-1_f64, 0.0015_f64, 0.1_f64)
+    }
+}
+
+impl Limiter {
+    pub fn new(ceiling: f64, look_ahead: f64, release: f64) -> Self {
+        let length = ((look_ahead * SAMPLE_RATE) as usize).max(1);
+        // a gain covers the two samples before it as well, the cubic peaks being that far
+        // behind ; the delay lets the average fully settle before the first of them comes
+        // out and the lowest is held until the last of them has gone
+        Self {
+            ceiling: ceiling.min(0_f64),
+            release,
+            delay: vec![(0_f64, 0_f64); length + 1],
+            gains: vec![1_f64; length + 2],
+            held: vec![1_f64; length],
+            held_sum: length as f64,
+            position: 0,
+            history: [[0_f64; 4]; 2],
+            gain: 1_f64
+        }
+    }
+
+    pub fn ceiling(&self) -> f64 {
+        self.ceiling
+    }
+
+    pub fn set_ceiling(&mut self, ceiling: f64) {
+        self.ceiling = ceiling.min(0_f64);
+    }
+
+    pub fn process(&mut self, (left, right): (f64, f64)) -> (f64, f64) {
+        for (history, sample) in self.history.iter_mut().zip([left, right]) {
+            history.rotate_left(1);
+            history[3] = sample;
+        }
+        // the cubic peaks lie between the two samples before this one, the plain sample keeps things tight
+        let peak = true_peak(&self.history[0]).max(true_peak(&self.history[1])).max(left.abs()).max(right.abs());
+        let ceiling = from_db(self.ceiling);
+        let index = self.position % self.gains.len();
+        self.gains[index] = if peak > ceiling { ceiling / peak } else { 1_f64 };
+
+        let lowest = self.gains.iter().fold(1_f64, |lowest, &gain| lowest.min(gain));
+        let index = self.position % self.held.len();
+        self.held_sum += lowest - self.held[index];
+        self.held[index] = lowest;
+        let target = (self.held_sum / self.held.len() as f64).min(1_f64);
+
+        self.gain = if target < self.gain { target } else { self.gain + (target - self.gain) * coefficient(self.release) };
+
+        let index = self.position % self.delay.len();
+        let (delayed_left, delayed_right) = self.delay[index];
+        self.delay[index] = (left, right);
+        self.position = self.position.wrapping_add(1);
+
+        (delayed_left * self.gain, delayed_right * self.gain)
+    }
+
+    pub fn gain_reduction(&self) -> f64 {
+        -to_db(self.gain)
+    }
+
+    pub fn reset(&mut self) {
+        self.delay.iter_mut().for_each(|frame| *frame = (0_f64, 0_f64));
+        self.gains.iter_mut().for_each(|gain| *gain = 1_f64);
+        self.held.iter_mut().for_each(|gain| *gain = 1_f64);
+        self.held_sum = self.held.len() as f64;
+        self.history = [[0_f64; 4]; 2];
+        self.gain = 1_f64;
+    }
+}
+
+// where the cubic clip starts bending, just above the default limiter's ceiling so
+// whatever the limiter lets through is left alone
+const CUBIC_KNEE: f64 = 0.9;
+
+// what happens to anything that still goes past full scale
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ClipMode {
+    #[default]
+    Hard, // flattened at full scale
+    Cubic, // leaves everything below the knee alone, then rounds off smoothly into full scale
+    Tanh, // saturates gradually, colouring loud passages well before they clip
+    Wrap // jumps round to the other side, harsh and on purpose
+}
+
+impl ClipMode {
+    pub fn apply(&self, sample: f64) -> f64 {
+        match self {
+            ClipMode::Hard => sample.clamp(-1_f64, 1_f64),
+            ClipMode::Cubic => {
+                // past the knee u - u³ / 3w² keeps the slope of 1 going and meets full scale flat
+                // at u = w, which is 1.5 times the headroom above the knee
+                let width = 1.5_f64 * (1_f64 - CUBIC_KNEE);
+                let u = (sample.abs() - CUBIC_KNEE).clamp(0_f64, width);
+                let level = if sample.abs() < CUBIC_KNEE { sample.abs() } else { CUBIC_KNEE + u - u * u * u / (3_f64 * width * width) };
+                level.copysign(sample)
+            },
+            ClipMode::Tanh => sample.tanh(),
+            ClipMode::Wrap => (sample + 1_f64).rem_euclid(2_f64) - 1_f64
+        }
+    }
+}
+
+// the last stage before the samples are turned into integers
+#[derive(Clone)]
+pub struct Dynamics {
+    pub compressor: Option<Compressor>,
+    pub limiter: Option<Limiter>,
+    pub clip_mode: ClipMode
+}
+
+impl Default for Dynamics {
+    // a limiter alone, transparent until the mix gets too loud
+    fn default() -> Self {
+        Self {
+            compressor: None,
+            limiter: Some(Limiter::default()),
+            clip_mode: ClipMode::Hard
+        }
+    }
+}
+
+impl Dynamics {
+    // for a mix of notes : the compressor rides the level as they pile up, the limiter stops
+    // anything getting through to the clip and anything it misses is rounded off rather
+    // than squared off
+    pub fn mix() -> Self {
+        Self {
+            compressor: Some(Compressor::new(-10_f64, 3_f64).with_makeup(2_f64)),
+            limiter: Some(Limiter::default()),
+            clip_mode: ClipMode::Cubic
+        }
+    }
+
+    // compressed and limited, but not yet clipped
+    pub fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        let frame = match &mut self.compressor {
+            Some(compressor) => compressor.process(frame),
+            None => frame
+        };
+        match &mut self.limiter {
+            Some(limiter) => limiter.process(frame),
+            None => frame
+        }
+    }
+
+    // dB taken off by the compressor and limiter together
+    pub fn gain_reduction(&self) -> f64 {
+        self.compressor.as_ref().map_or(0_f64, |compressor| compressor.gain_reduction()) +
+            self.limiter.as_ref().map_or(0_f64, |limiter| limiter.gain_reduction())
+    }
+}
+
+impl Effect for Compressor {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        Compressor::process(self, frame)
+    }
+
+    fn reset(&mut self) {
+        Compressor::reset(self)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            ("threshold".to_string(), self.threshold),
+            ("ratio".to_string(), self.ratio),
+            ("attack".to_string(), self.attack),
+            ("release".to_string(), self.release),
+            ("knee".to_string(), self.knee),
+            ("makeup".to_string(), self.makeup)
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "threshold" => self.threshold = value,
+            "ratio" => self.ratio = value,
+            "attack" => self.attack = value,
+            "release" => self.release = value,
+            "knee" => self.knee = value,
+            "makeup" => self.makeup = value,
+            _ => return false
+        }
+        true
+    }
+}
+
+impl Effect for Limiter {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        Limiter::process(self, frame)
+    }
+
+    fn reset(&mut self) {
+        Limiter::reset(self)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            ("ceiling".to_string(), self.ceiling),
+            ("release".to_string(), self.release)
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "ceiling" => self.set_ceiling(value),
+            "release" => self.release = value,
+            _ => return false
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    // the limiter's output for a stereo signal lasting seconds
+    fn limit(limiter: &mut Limiter, seconds: f64, f: impl Fn(f64) -> (f64, f64)) -> Vec<(f64, f64)> {
+        (0..(seconds * SAMPLE_RATE) as usize).map(|i| limiter.process(f(i as f64 / SAMPLE_RATE))).collect()
+    }
+
+    #[test]
+    fn limiter_keeps_samples_under_the_ceiling() {
+        let ceiling = from_db(-1_f64);
+        let signals: [fn(f64) -> (f64, f64); 3] = [
+            |t| ((2_f64 * PI * 440_f64 * t).sin() * 4_f64, 0_f64),
+            |t| (0_f64, if (t * 100_f64) as i64 % 2 == 0 { 3_f64 } else { -3_f64 }),
+            |t| if (t * SAMPLE_RATE) as i64 % 1000 == 500 { (10_f64, -10_f64) } else { (0.1_f64, 0.1_f64) }
+        ];
+        for f in signals {
+            let mut limiter = Limiter::default();
+            for (left, right) in limit(&mut limiter, 0.5_f64, f) {
+                assert!(left.abs() <= ceiling + 1e-12_f64 && right.abs() <= ceiling + 1e-12_f64, "{} {}", left, right);
+            }
+        }
+    }
+
+    #[test]
+    fn limiter_catches_peaks_between_samples() {
+        // a sine near a quarter of the sample rate peaks well above its samples
+        let mut limiter = Limiter::new(-1_f64, 0.0015_f64, 0.1_f64);
+        let output = limit(&mut limiter, 0.5_f64, |t| {
+            let x = (2_f64 * PI * 11025_f64 * t + PI / 4_f64).sin() * 2_f64;
+            (x, x)
+        });
+        let left: Vec<f64> = output.iter().map(|&(left, _)| left).collect();
+        let peak = left.windows(4).skip(1000).fold(0_f64, |peak, window| peak.max(true_peak(&[window[0], window[1], window[2], window[3]])));
+        assert!(peak <= from_db(-1_f64) * 1.01_f64, "{}", peak);
+    }
+
+    #[test]
+    fn limiter_leaves_quiet_signals_alone() {
+        let mut limiter = Limiter::default();
+        let f = |t: f64| ((2_f64 * PI * 220_f64 * t).sin() * 0.5_f64, (2_f64 * PI * 330_f64 * t).sin() * 0.5_f64);
+        let output = limit(&mut limiter, 0.1_f64, f);
+        // only delayed by the look ahead
+        let delay = limiter.delay.len();
+        for (i, &frame) in output.iter().enumerate().skip(delay) {
+            assert_eq!(frame, f((i - delay) as f64 / SAMPLE_RATE));
+        }
+        assert_eq!(limiter.gain_reduction(), 0_f64);
+    }
+
+    #[test]
+    fn limiter_ceiling_stays_at_or_below_full_scale() {
+        let mut limiter = Limiter::new(3_f64, 0.0015_f64, 0.1_f64);
+        assert_eq!(limiter.ceiling(), 0_f64);
+        limiter.set_ceiling(-6_f64);
+        assert_eq!(limiter.ceiling(), -6_f64);
+        for (left, _) in limit(&mut limiter, 0.2_f64, |_| (1_f64, 0_f64)) {
+            assert!(left <= from_db(-6_f64) + 1e-12_f64);
+        }
+    }
+
+    #[test]
+    fn cubic_clip_is_transparent_below_the_knee() {
+        for i in -90..=90 {
+            let x = i as f64 / 100_f64;
+            assert_eq!(ClipMode::Cubic.apply(x), x);
+        }
+    }
+
+    #[test]
+    fn cubic_clip_rounds_off_into_full_scale() {
+        let mut last = ClipMode::Cubic.apply(CUBIC_KNEE);
+        for i in 1..=1000 {
+            let level = ClipMode::Cubic.apply(CUBIC_KNEE + i as f64 / 1000_f64);
+            assert!(level >= last && level <= 1_f64);
+            last = level;
+        }
+        assert!((ClipMode::Cubic.apply(CUBIC_KNEE + 1.5_f64 * (1_f64 - CUBIC_KNEE)) - 1_f64).abs() < 1e-12_f64);
+        assert_eq!(ClipMode::Cubic.apply(5_f64), 1_f64);
+        assert_eq!(ClipMode::Cubic.apply(-5_f64), -1_f64);
+    }
+
+    #[test]
+    fn other_clips_stay_in_range() {
+        for i in -300..=300 {
+            let x = i as f64 / 100_f64;
+            for clip_mode in [ClipMode::Hard, ClipMode::Tanh, ClipMode::Wrap] {
+                assert!(clip_mode.apply(x).abs() <= 1_f64);
+            }
+        }
+        assert_eq!(ClipMode::Hard.apply(0.5_f64), 0.5_f64);
+        assert!((ClipMode::Wrap.apply(1.5_f64) + 0.5_f64).abs() < 1e-12_f64);
+    }
+
+    #[test]
+    fn compressor_takes_off_the_ratio_above_the_threshold() {
+        let compressor = Compressor { knee: 0_f64, ..Compressor::new(-12_f64, 4_f64) };
+        assert_eq!(compressor.static_reduction(-20_f64), 0_f64);
+        assert!((compressor.static_reduction(0_f64) - 9_f64).abs() < 1e-12_f64);
+
+        let mut compressor = compressor;
+        for _ in 0..44100 {
+            compressor.process((1_f64, -1_f64));
+        }
+        assert!((compressor.gain_reduction() - 9_f64).abs() < 1e-6_f64);
+    }
+}
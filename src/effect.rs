@@ -0,0 +1,194 @@
+use crate::oscillator::width;
+
+// anything that turns a stereo stream into another one, sample by sample
+pub trait Effect: Send {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64);
+
+    // forgets everything it has heard, ready for a new sound
+    fn reset(&mut self);
+
+    // names and current values of the settings that can be changed while playing
+    fn parameters(&self) -> Vec<(String, f64)>;
+
+    // false when the effect has no parameter called name
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool;
+
+    fn parameter(&self, name: &str) -> Option<f64> {
+        self.parameters().into_iter().find(|(parameter, _)| parameter == name).map(|(_, value)| value)
+    }
+}
+
+// stereo width as an effect, for the end of a chain
+pub struct Width {
+    pub width: f64
+}
+
+impl Effect for Width {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        width(frame, self.width)
+    }
+
+    fn reset(&mut self) {}
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![("width".to_string(), self.width)]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "width" => self.width = value,
+            _ => return false
+        }
+        true
+    }
+}
+
+fn run(chain: &mut [Box<dyn Effect>], frame: (f64, f64)) -> (f64, f64) {
+    chain.iter_mut().fold(frame, |frame, effect| effect.process(frame))
+}
+
+// what one instrument goes through on its way to the master
+pub struct Channel {
+    pub inserts: Vec<Box<dyn Effect>>,
+    pub sends: Vec<f64>, // level into each bus, after the inserts and the volume
+    pub volume: f64,
+    input: (f64, f64)
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Channel {
+    pub fn new() -> Self {
+        Self { inserts: Vec::new(), sends: Vec::new(), volume: 1_f64, input: (0_f64, 0_f64) }
+    }
+
+    pub fn with_insert(mut self, effect: impl Effect + 'static) -> Self {
+        self.inserts.push(Box::new(effect));
+        self
+    }
+
+    pub fn with_send(mut self, bus: usize, level: f64) -> Self {
+        if self.sends.len() <= bus {
+            self.sends.resize(bus + 1, 0_f64);
+        }
+        self.sends[bus] = level;
+        self
+    }
+
+    pub fn with_volume(mut self, volume: f64) -> Self {
+        self.volume = volume;
+        self
+    }
+}
+
+// a shared effect that channels send some of themselves to, usually fully wet,
+// and whose output is returned to the master
+pub struct Bus {
+    pub inserts: Vec<Box<dyn Effect>>,
+    pub level: f64, // how much of the bus comes back
+    input: (f64, f64)
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self { inserts: Vec::new(), level: 1_f64, input: (0_f64, 0_f64) }
+    }
+
+    pub fn with_insert(mut self, effect: impl Effect + 'static) -> Self {
+        self.inserts.push(Box::new(effect));
+        self
+    }
+}
+
+// channels into buses into the master chain. Instruments add their notes to their
+// channel as they are rendered, then mix runs every chain once and starts over
+pub struct Mixer {
+    pub channels: Vec<Channel>,
+    pub buses: Vec<Bus>,
+    pub master: Vec<Box<dyn Effect>>,
+    pub volume: f64
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self { channels: Vec::new(), buses: Vec::new(), master: Vec::new(), volume: 1_f64 }
+    }
+
+    // index of the new channel
+    pub fn add_channel(&mut self, channel: Channel) -> usize {
+        self.channels.push(channel);
+        self.channels.len() - 1
+    }
+
+    // index of the new bus, for the channels' sends
+    pub fn add_bus(&mut self, bus: Bus) -> usize {
+        self.buses.push(bus);
+        self.buses.len() - 1
+    }
+
+    pub fn with_master_insert(mut self, effect: impl Effect + 'static) -> Self {
+        self.master.push(Box::new(effect));
+        self
+    }
+
+    pub fn with_volume(mut self, volume: f64) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn add(&mut self, channel: usize, (left, right): (f64, f64)) {
+        if let Some(channel) = self.channels.get_mut(channel) {
+            channel.input.0 += left;
+            channel.input.1 += right;
+        }
+    }
+
+    pub fn mix(&mut self) -> (f64, f64) {
+        let buses = &mut self.buses;
+        let mut output = (0_f64, 0_f64);
+
+        for channel in self.channels.iter_mut() {
+            let (left, right) = run(&mut channel.inserts, channel.input);
+            let (left, right) = (left * channel.volume, right * channel.volume);
+            channel.input = (0_f64, 0_f64);
+
+            for (bus, level) in buses.iter_mut().zip(channel.sends.iter()) {
+                bus.input.0 += left * level;
+                bus.input.1 += right * level;
+            }
+            output = (output.0 + left, output.1 + right);
+        }
+
+        for bus in buses.iter_mut() {
+            let (left, right) = run(&mut bus.inserts, bus.input);
+            bus.input = (0_f64, 0_f64);
+            output = (output.0 + left * bus.level, output.1 + right * bus.level);
+        }
+
+        let (left, right) = run(&mut self.master, output);
+        (left * self.volume, right * self.volume)
+    }
+
+    pub fn reset(&mut self) {
+        let chains = self.channels.iter_mut().flat_map(|channel| channel.inserts.iter_mut())
+            .chain(self.buses.iter_mut().flat_map(|bus| bus.inserts.iter_mut()))
+            .chain(self.master.iter_mut());
+        chains.for_each(|effect| effect.reset());
+    }
+}
@@ -0,0 +1,611 @@
+use serde::{Deserialize, Serialize};
+use crate::instrument::Note;
+
+// how an envelope segment travels from its start level to its end level
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum Curve {
+    #[default]
+    Linear,
+    Exponential, // moves fast at first then eases into the end level, like a capacitor charging
+    Logarithmic, // starts slowly then speeds up towards the end level
+    Tension(f64) // from -1 (logarithmic) through 0 (linear) to 1 (exponential)
+}
+
+impl Curve {
+    // how far along the segment the level is, both from 0 to 1
+    pub fn shape(&self, progress: f64) -> f64 {
+        let progress = progress.clamp(0_f64, 1_f64);
+        let tension = match *self {
+            Curve::Linear => 0_f64,
+            Curve::Exponential => 1_f64,
+            Curve::Logarithmic => -1_f64,
+            Curve::Tension(tension) => tension.clamp(-1_f64, 1_f64)
+        };
+
+        if tension.abs() < 1e-6_f64 {
+            progress
+        } else {
+            // normalised so every curve still starts at 0 and lands exactly on 1
+            let k = -6_f64 * tension;
+            ((k * progress).exp() - 1_f64) / (k.exp() - 1_f64)
+        }
+    }
+
+    pub fn interpolate(&self, from: f64, to: f64, progress: f64) -> f64 {
+        from + (to - from) * self.shape(progress)
+    }
+}
+
+// what happens when a key is pressed again while its note is still sounding
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum RetriggerMode {
+    #[default]
+    Retrigger, // attack again, starting from wherever the envelope had got to
+    Legato, // no new attack, glide from the current level back to the sustain level
+    Reset // attack again from silence, the way it always used to be
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)] // anything left out is taken from the default envelope
+pub struct EnvelopeADSR {
+    pub attack_time: f64,
+    pub decay_time: f64,
+    pub release_time: f64,
+
+    pub sustain_amplitude: f64,
+    pub start_amplitude: f64,
+
+    #[serde(default)]
+    pub attack_curve: Curve,
+    #[serde(default)]
+    pub decay_curve: Curve,
+    #[serde(default)]
+    pub release_curve: Curve,
+
+    #[serde(default)]
+    pub retrigger_mode: RetriggerMode
+}
+
+impl Default for EnvelopeADSR {
+    fn default() -> Self {
+        Self {
+            attack_time: 0.1_f64,
+            decay_time: 0.1_f64,
+            release_time: 0.2_f64,
+
+            sustain_amplitude: 1_f64,
+            start_amplitude: 1_f64,
+
+            attack_curve: Curve::Linear,
+            decay_curve: Curve::Linear,
+            release_curve: Curve::Linear,
+
+            retrigger_mode: RetriggerMode::Retrigger
+        }
+    }
+}
+
+// how far through a segment of the given length we are, a segment with no
+// length is over as soon as it starts
+fn progress(elapsed: f64, length: f64) -> f64 {
+    if length > 0_f64 { elapsed / length } else { 1_f64 }
+}
+
+// where an envelope starts from when its note is triggered ; every envelope of a
+// voice keeps its own, so each one carries on from wherever it had got to
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Trigger {
+    pub start_level: f64, // level the envelope was at when the note was last triggered
+    pub legato: bool // retriggered without a new attack
+}
+
+impl RetriggerMode {
+    // how an envelope that had got to level starts again
+    fn trigger(self, level: f64) -> Trigger {
+        match self {
+            RetriggerMode::Retrigger => Trigger { start_level: level, legato: false },
+            RetriggerMode::Legato => Trigger { start_level: level, legato: true },
+            RetriggerMode::Reset => Trigger::default()
+        }
+    }
+}
+
+// restarts the note, its envelope picking up from trigger
+fn retrigger(trigger: Trigger, n: &mut Note, time: f64) {
+    n.trigger = trigger;
+    n.on = time;
+    n.active = true;
+}
+
+impl EnvelopeADSR {
+    // level life_time seconds after the key was pressed, as long as it is held ;
+    // start_level is where the envelope was when the key went down
+    fn held_amplitude(&self, life_time: f64, start_level: f64, legato: bool) -> f64 {
+        if legato { // straight back to sustain, no attack
+            if life_time < self.decay_time {
+                self.decay_curve.interpolate(start_level, self.sustain_amplitude, progress(life_time, self.decay_time))
+            } else {
+                self.sustain_amplitude
+            }
+        } else if life_time < self.attack_time { // attack
+            self.attack_curve.interpolate(start_level, self.start_amplitude, progress(life_time, self.attack_time))
+        } else if life_time < self.attack_time + self.decay_time { // decay
+            self.decay_curve.interpolate(self.start_amplitude, self.sustain_amplitude, progress(life_time - self.attack_time, self.decay_time))
+        } else { // sustain
+            self.sustain_amplitude
+        }
+    }
+
+    fn amplitude_from(&self, time: f64, time_on: f64, time_off: f64, start_level: f64, legato: bool) -> f64 {
+        let amplitude = if time_on > time_off { // note is on
+            self.held_amplitude(time - time_on, start_level, legato)
+        } else { // note is off, release from wherever the note had got to
+            let release_amplitude = self.held_amplitude(time_off - time_on, start_level, legato);
+            self.release_curve.interpolate(release_amplitude, 0_f64, progress(time - time_off, self.release_time))
+        };
+
+        // every segment lands exactly on its end level, so there is no need to
+        // gate small levels to silence
+        f64::max(amplitude, 0_f64)
+    }
+
+    pub fn amplitude(&self, time: f64, time_on: f64, time_off: f64) -> f64 {
+        self.amplitude_from(time, time_on, time_off, 0_f64, false)
+    }
+
+    // silent for good: released and faded out, or decayed to nothing while still held
+    pub fn finished(&self, time: f64, n: Note) -> bool {
+        let attack_time = if n.trigger.legato { 0_f64 } else { self.attack_time };
+        let done = n.off >= n.on || time - n.on >= attack_time + self.decay_time;
+        done && self.note_amplitude(time, n) <= 0_f64
+    }
+
+    // level of the note's envelope, taking into account where it was when the note was retriggered
+    pub fn note_amplitude(&self, time: f64, n: Note) -> f64 {
+        self.amplitude_from(time, n.on, n.off, n.trigger.start_level, n.trigger.legato)
+    }
+
+    // where the envelope starts again from if the note is retriggered at time
+    pub fn trigger(&self, time: f64, n: Note) -> Trigger {
+        self.retrigger_mode.trigger(self.note_amplitude(time, n))
+    }
+
+    // the key of a note that is still sounding has been pressed again
+    pub fn retrigger(&self, n: &mut Note, time: f64) {
+        retrigger(self.trigger(time, *n), n, time);
+    }
+}
+
+// how long a breakpoint envelope with nothing after its held stages takes to fade out
+// once released, so letting go doesn't click
+const DECLICK_TIME: f64 = 0.005;
+
+// one segment of a breakpoint envelope, travelling to level over time seconds
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stage {
+    pub time: f64,
+    pub level: f64,
+    #[serde(default)]
+    pub curve: Curve
+}
+
+impl Stage {
+    pub fn new(time: f64, level: f64, curve: Curve) -> Self {
+        Self { time, level, curve }
+    }
+}
+
+// any number of stages starting from silence, or from wherever a retriggered note had
+// got to, after an optional delay that holds that level. While the key is held the envelope
+// stops at the end of the sustain stage, or cycles through the loop stages if it
+// reaches the end of the loop first ; once released it carries on from wherever
+// it had got to with the stages after the sustain stage (or after the loop), or
+// fades out quickly if there are none. Without either the envelope runs through
+// all its stages whatever the key does
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnvelopeBreakpoint {
+    #[serde(default)]
+    pub delay: f64, // seconds before the first stage starts
+    pub stages: Vec<Stage>,
+    #[serde(default)]
+    pub sustain: Option<usize>, // index of the stage whose level is held
+    #[serde(default)]
+    pub loop_points: Option<(usize, usize)>, // first and last stage of the loop, both included
+
+    #[serde(default)]
+    pub retrigger_mode: RetriggerMode
+}
+
+impl EnvelopeBreakpoint {
+    pub fn new(stages: Vec<Stage>) -> Self {
+        Self { stages, ..Default::default() }
+    }
+
+    pub fn with_sustain(mut self, stage: usize) -> Self {
+        self.sustain = Some(stage);
+        self
+    }
+
+    pub fn with_loop(mut self, first: usize, last: usize) -> Self {
+        self.loop_points = Some((first, last));
+        self
+    }
+
+    pub fn with_delay(mut self, delay: f64) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    // delay, attack, hold, decay, sustain and release ; level is the sustain level
+    pub fn dahdsr(delay: f64, attack: f64, hold: f64, decay: f64, sustain: f64, release: f64) -> Self {
+        Self::new(vec![
+            Stage::new(attack, 1_f64, Curve::Linear),
+            Stage::new(hold, 1_f64, Curve::Linear),
+            Stage::new(decay, sustain, Curve::Exponential),
+            Stage::new(release, 0_f64, Curve::Exponential)
+        ]).with_sustain(2).with_delay(delay)
+    }
+
+    // the last stage that plays while the key is held, if the key makes any difference at all
+    fn held_end(&self) -> Option<usize> {
+        let last = self.stages.len().checked_sub(1)?;
+        match (self.sustain, self.loop_points) {
+            (Some(sustain), Some((_, end))) => Some(usize::min(sustain, end).min(last)),
+            (Some(sustain), None) => Some(sustain.min(last)),
+            (None, Some((_, end))) => Some(end.min(last)),
+            (None, None) => None
+        }
+    }
+
+    fn looping(&self) -> Option<(usize, usize)> {
+        let (start, end) = self.loop_points?;
+        if start <= end && end < self.stages.len() && !matches!(self.sustain, Some(sustain) if end > sustain) {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    // walks stages first..=last starting from level from ; Err carries what is left of
+    // elapsed and the level reached when it runs past the last of them
+    fn walk(&self, first: usize, last: usize, mut elapsed: f64, mut from: f64) -> Result<f64, (f64, f64)> {
+        for stage in self.stages.iter().take(last + 1).skip(first) {
+            if elapsed < stage.time {
+                return Ok(stage.curve.interpolate(from, stage.level, progress(elapsed, stage.time)));
+            }
+            elapsed -= stage.time;
+            from = stage.level;
+        }
+        Err((elapsed, from))
+    }
+
+    // level life_time seconds after the key was pressed, as long as it is held
+    fn held_amplitude(&self, life_time: f64, start_level: f64, legato: bool) -> f64 {
+        // legato skips the delay along with the stages before sustain
+        let life_time = if legato { life_time } else { life_time - self.delay };
+        if life_time < 0_f64 {
+            return start_level;
+        }
+        let end = match self.held_end() {
+            Some(end) => end,
+            None => return self.walk(0, self.stages.len(), life_time, start_level).unwrap_or_else(|(_, level)| level)
+        };
+        // legato glides straight into the sustain stage
+        let first = if legato { self.sustain.unwrap_or(0).min(end) } else { 0 };
+
+        match self.walk(first, end, life_time, start_level) {
+            Ok(level) => level,
+            Err((elapsed, level)) => match self.looping() {
+                Some((start, end)) => {
+                    let length: f64 = self.stages[start..=end].iter().map(|stage| stage.time).sum();
+                    if length > 0_f64 {
+                        let from = self.stages[end].level;
+                        self.walk(start, end, elapsed % length, from).unwrap_or(from)
+                    } else {
+                        level
+                    }
+                },
+                None => level
+            }
+        }
+    }
+
+    fn amplitude_from(&self, time: f64, time_on: f64, time_off: f64, start_level: f64, legato: bool) -> f64 {
+        let amplitude = match self.held_end() {
+            Some(end) if time_on <= time_off => {
+                // released, the stages after the held ones take over from the level reached
+                let release_amplitude = self.held_amplitude(time_off - time_on, start_level, legato);
+                if end + 1 < self.stages.len() {
+                    self.walk(end + 1, self.stages.len(), time - time_off, release_amplitude).unwrap_or_else(|(_, level)| level)
+                } else {
+                    release_amplitude * (1_f64 - progress(time - time_off, DECLICK_TIME)).clamp(0_f64, 1_f64)
+                }
+            },
+            _ => self.held_amplitude(time - time_on, start_level, legato)
+        };
+
+        f64::max(amplitude, 0_f64)
+    }
+
+    pub fn amplitude(&self, time: f64, time_on: f64, time_off: f64) -> f64 {
+        self.amplitude_from(time, time_on, time_off, 0_f64, false)
+    }
+
+    pub fn note_amplitude(&self, time: f64, n: Note) -> f64 {
+        self.amplitude_from(time, n.on, n.off, n.trigger.start_level, n.trigger.legato)
+    }
+
+    // silent for good: every stage that is going to play has played out
+    pub fn finished(&self, time: f64, n: Note) -> bool {
+        let length = |stages: &[Stage]| stages.iter().map(|stage| stage.time).sum::<f64>();
+        let done = match self.held_end() {
+            Some(end) if n.off >= n.on => time - n.off >= if end + 1 < self.stages.len() {
+                length(&self.stages[end + 1..])
+            } else {
+                DECLICK_TIME
+            },
+            Some(_) => false,
+            None => time - n.on >= self.delay + length(&self.stages)
+        };
+        done && self.note_amplitude(time, n) <= 0_f64
+    }
+
+    pub fn trigger(&self, time: f64, n: Note) -> Trigger {
+        self.retrigger_mode.trigger(self.note_amplitude(time, n))
+    }
+
+    pub fn retrigger(&self, n: &mut Note, time: f64) {
+        retrigger(self.trigger(time, *n), n, time);
+    }
+}
+
+// either kind of envelope, wherever an envelope is wanted
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Envelope {
+    ADSR(EnvelopeADSR),
+    Breakpoint(EnvelopeBreakpoint)
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Envelope::ADSR(EnvelopeADSR::default())
+    }
+}
+
+impl From<EnvelopeADSR> for Envelope {
+    fn from(envelope: EnvelopeADSR) -> Self {
+        Envelope::ADSR(envelope)
+    }
+}
+
+impl From<EnvelopeBreakpoint> for Envelope {
+    fn from(envelope: EnvelopeBreakpoint) -> Self {
+        Envelope::Breakpoint(envelope)
+    }
+}
+
+impl Envelope {
+    pub fn amplitude(&self, time: f64, time_on: f64, time_off: f64) -> f64 {
+        match self {
+            Envelope::ADSR(envelope) => envelope.amplitude(time, time_on, time_off),
+            Envelope::Breakpoint(envelope) => envelope.amplitude(time, time_on, time_off)
+        }
+    }
+
+    pub fn note_amplitude(&self, time: f64, n: Note) -> f64 {
+        match self {
+            Envelope::ADSR(envelope) => envelope.note_amplitude(time, n),
+            Envelope::Breakpoint(envelope) => envelope.note_amplitude(time, n)
+        }
+    }
+
+    pub fn finished(&self, time: f64, n: Note) -> bool {
+        match self {
+            Envelope::ADSR(envelope) => envelope.finished(time, n),
+            Envelope::Breakpoint(envelope) => envelope.finished(time, n)
+        }
+    }
+
+    pub fn trigger(&self, time: f64, n: Note) -> Trigger {
+        match self {
+            Envelope::ADSR(envelope) => envelope.trigger(time, n),
+            Envelope::Breakpoint(envelope) => envelope.trigger(time, n)
+        }
+    }
+
+    pub fn retrigger(&self, n: &mut Note, time: f64) {
+        match self {
+            Envelope::ADSR(envelope) => envelope.retrigger(n, time),
+            Envelope::Breakpoint(envelope) => envelope.retrigger(n, time)
+        }
+    }
+
+    pub fn set_retrigger_mode(&mut self, retrigger_mode: RetriggerMode) {
+        match self {
+            Envelope::ADSR(envelope) => envelope.retrigger_mode = retrigger_mode,
+            Envelope::Breakpoint(envelope) => envelope.retrigger_mode = retrigger_mode
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9_f64
+    }
+
+    // a key pressed at on and still held, the way instruments start their notes
+    fn held(on: f64) -> Note {
+        Note { on, pressed: on, active: true, ..Default::default() }
+    }
+
+    fn adsr(retrigger_mode: RetriggerMode) -> EnvelopeADSR {
+        EnvelopeADSR {
+            attack_time: 0.1_f64,
+            decay_time: 0.2_f64,
+            release_time: 0.4_f64,
+            sustain_amplitude: 0.5_f64,
+            start_amplitude: 1_f64,
+            retrigger_mode,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn curves_start_at_0_and_land_on_1() {
+        for curve in [Curve::Linear, Curve::Exponential, Curve::Logarithmic, Curve::Tension(0.3_f64), Curve::Tension(-0.7_f64)] {
+            assert_eq!(curve.shape(0_f64), 0_f64);
+            assert!(close(curve.shape(1_f64), 1_f64));
+        }
+        assert!(Curve::Exponential.shape(0.5_f64) > 0.5_f64);
+        assert!(Curve::Logarithmic.shape(0.5_f64) < 0.5_f64);
+    }
+
+    #[test]
+    fn adsr_walks_its_segments() {
+        let envelope = adsr(RetriggerMode::Retrigger);
+        assert!(close(envelope.amplitude(0.05_f64, 0_f64, -1_f64), 0.5_f64)); // half way up the attack
+        assert!(close(envelope.amplitude(0.2_f64, 0_f64, -1_f64), 0.75_f64)); // half way down the decay
+        assert!(close(envelope.amplitude(5_f64, 0_f64, -1_f64), 0.5_f64)); // sustain
+        // released during the attack, it falls from where it had got to
+        assert!(close(envelope.amplitude(0.25_f64, 0_f64, 0.05_f64), 0.25_f64));
+        assert_eq!(envelope.amplitude(0.5_f64, 0_f64, 0.05_f64), 0_f64);
+    }
+
+    #[test]
+    fn adsr_finishes_once_released_and_faded() {
+        let envelope = adsr(RetriggerMode::Retrigger);
+        let mut n = held(1_f64);
+        assert!(!envelope.finished(10_f64, n));
+        n.off = 2_f64;
+        assert!(!envelope.finished(2.3_f64, n));
+        assert!(envelope.finished(2.5_f64, n));
+
+        // one that decays to nothing is done while still held
+        let envelope = EnvelopeADSR { sustain_amplitude: 0_f64, ..adsr(RetriggerMode::Retrigger) };
+        assert!(!envelope.finished(1.2_f64, held(1_f64)));
+        assert!(envelope.finished(1.4_f64, held(1_f64)));
+    }
+
+    #[test]
+    fn retrigger_picks_up_where_the_envelope_was() {
+        let envelope = adsr(RetriggerMode::Retrigger);
+        let mut n = held(1_f64);
+        n.off = 2_f64;
+        let level = envelope.note_amplitude(2.2_f64, n);
+        envelope.retrigger(&mut n, 2.2_f64);
+
+        assert_eq!(n.on, 2.2_f64);
+        assert_eq!(n.pressed, 1_f64);
+        assert!(n.on > n.off);
+        assert!(close(envelope.note_amplitude(2.2_f64, n), level));
+        // and attacks from there
+        assert!(close(envelope.note_amplitude(2.3_f64, n), 1_f64));
+    }
+
+    #[test]
+    fn legato_glides_back_to_sustain_without_an_attack() {
+        let envelope = adsr(RetriggerMode::Legato);
+        let mut n = held(1_f64);
+        n.off = 2_f64;
+        let level = envelope.note_amplitude(2.2_f64, n);
+        envelope.retrigger(&mut n, 2.2_f64);
+
+        assert!(n.trigger.legato);
+        assert!(close(envelope.note_amplitude(2.2_f64, n), level));
+        assert!(close(envelope.note_amplitude(2.3_f64, n), (level + 0.5_f64) / 2_f64));
+        assert!(close(envelope.note_amplitude(2.4_f64, n), 0.5_f64));
+    }
+
+    #[test]
+    fn reset_attacks_from_silence() {
+        let envelope = adsr(RetriggerMode::Reset);
+        let mut n = held(1_f64);
+        envelope.retrigger(&mut n, 2_f64);
+        assert_eq!(n.trigger, Trigger::default());
+        assert_eq!(envelope.note_amplitude(2_f64, n), 0_f64);
+    }
+
+    fn breakpoint() -> EnvelopeBreakpoint {
+        EnvelopeBreakpoint::new(vec![
+            Stage::new(0.1_f64, 1_f64, Curve::Linear),
+            Stage::new(0.2_f64, 0.6_f64, Curve::Linear),
+            Stage::new(0.4_f64, 0_f64, Curve::Linear)
+        ])
+    }
+
+    #[test]
+    fn breakpoint_holds_the_sustain_stage_and_releases_from_there() {
+        let envelope = breakpoint().with_sustain(1);
+        assert!(close(envelope.amplitude(0.05_f64, 0_f64, -1_f64), 0.5_f64));
+        assert!(close(envelope.amplitude(0.2_f64, 0_f64, -1_f64), 0.8_f64));
+        assert!(close(envelope.amplitude(5_f64, 0_f64, -1_f64), 0.6_f64));
+        // released during the first stage, the last stage takes over from its level
+        assert!(close(envelope.amplitude(0.25_f64, 0_f64, 0.05_f64), 0.25_f64));
+
+        let mut n = held(1_f64);
+        n.off = 2_f64;
+        assert!(!envelope.finished(2.3_f64, n));
+        assert!(envelope.finished(2.5_f64, n));
+    }
+
+    #[test]
+    fn breakpoint_without_sustain_ignores_the_key() {
+        let envelope = breakpoint();
+        assert!(close(envelope.amplitude(0.5_f64, 0_f64, 0.05_f64), 0.3_f64));
+        assert!(!envelope.finished(1.6_f64, held(1_f64)));
+        assert!(envelope.finished(1.8_f64, held(1_f64)));
+    }
+
+    #[test]
+    fn breakpoint_loops_while_held() {
+        let envelope = breakpoint().with_loop(0, 1);
+        assert!(close(envelope.amplitude(0.35_f64, 0_f64, -1_f64), 0.8_f64)); // half way up the second time round
+        assert!(close(envelope.amplitude(0.5_f64, 0_f64, -1_f64), 0.8_f64));
+        assert!(close(envelope.amplitude(0.8_f64, 0_f64, 0.5_f64), 0.2_f64));
+    }
+
+    #[test]
+    fn breakpoint_declicks_when_nothing_follows_the_held_stages() {
+        let envelope = breakpoint().with_sustain(1).with_loop(0, 1);
+        let envelope = EnvelopeBreakpoint { stages: envelope.stages[..2].to_vec(), ..envelope };
+        let n = Note { off: 5_f64, ..held(1_f64) };
+        let level = envelope.note_amplitude(5_f64, n);
+        assert!(level > 0_f64);
+        assert!(close(envelope.note_amplitude(5_f64 + DECLICK_TIME / 2_f64, n), level / 2_f64));
+        assert!(!envelope.finished(5_f64 + DECLICK_TIME / 2_f64, n));
+        assert!(envelope.finished(5_f64 + 2_f64 * DECLICK_TIME, n));
+    }
+
+    #[test]
+    fn dahdsr_delay_holds_the_start_level() {
+        let envelope = EnvelopeBreakpoint::dahdsr(0.5_f64, 0.1_f64, 0.2_f64, 0.3_f64, 0.4_f64, 0.5_f64);
+        assert_eq!(envelope.amplitude(0.3_f64, 0_f64, -1_f64), 0_f64);
+        assert!(close(envelope.amplitude(0.55_f64, 0_f64, -1_f64), 0.5_f64));
+        assert!(close(envelope.amplitude(0.7_f64, 0_f64, -1_f64), 1_f64));
+        assert!(close(envelope.amplitude(5_f64, 0_f64, -1_f64), 0.4_f64));
+
+        // retriggered, it waits at the level it had got to
+        let mut n = Note { off: 5_f64, ..held(1_f64) };
+        envelope.retrigger(&mut n, 5.25_f64);
+        let level = n.trigger.start_level;
+        assert!(level > 0_f64 && level < 0.4_f64);
+        assert!(close(envelope.note_amplitude(5.5_f64, n), level));
+        assert!(envelope.note_amplitude(5.8_f64, n) > level);
+    }
+
+    #[test]
+    fn dahdsr_legato_skips_the_delay() {
+        let envelope = EnvelopeBreakpoint { retrigger_mode: RetriggerMode::Legato, ..EnvelopeBreakpoint::dahdsr(0.5_f64, 0.1_f64, 0.2_f64, 0.3_f64, 0.4_f64, 0.5_f64) };
+        let mut n = held(1_f64);
+        envelope.retrigger(&mut n, 1.7_f64);
+        assert!(n.trigger.legato);
+        assert!(close(envelope.note_amplitude(1.7_f64, n), 1_f64));
+        assert!(envelope.note_amplitude(1.8_f64, n) < 1_f64);
+        assert!(close(envelope.note_amplitude(5_f64, n), 0.4_f64));
+    }
+}
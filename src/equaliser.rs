@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use crate::filter::*;
+use crate::effect::Effect;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum BandType {
+    Bell,
+    LowShelf,
+    HighShelf,
+    HighPass, // gain is ignored by the passes
+    LowPass
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Band {
+    pub band_type: BandType,
+    pub frequency: f64,
+    pub gain: f64, // dB
+    pub q: f64,
+    pub enabled: bool
+}
+
+impl Band {
+    pub fn new(band_type: BandType, frequency: f64, gain: f64, q: f64) -> Self {
+        Self { band_type, frequency, gain, q, enabled: true }
+    }
+
+    fn filter_type(&self) -> FilterType {
+        match self.band_type {
+            BandType::Bell => FilterType::Peaking(self.gain),
+            BandType::LowShelf => FilterType::LowShelf(self.gain),
+            BandType::HighShelf => FilterType::HighShelf(self.gain),
+            BandType::HighPass => FilterType::HighPass,
+            BandType::LowPass => FilterType::LowPass
+        }
+    }
+}
+
+// any number of bands one after the other, the same on both sides
+#[derive(Clone)]
+pub struct Equaliser {
+    bands: Vec<Band>,
+    filters: [Vec<Filter>; 2]
+}
+
+impl Equaliser {
+    pub fn new(bands: Vec<Band>) -> Self {
+        let filters = bands.iter()
+            .map(|band| Filter::new(band.filter_type(), band.frequency, band.q, 1))
+            .collect::<Vec<Filter>>();
+        Self { bands, filters: [filters.clone(), filters] }
+    }
+
+    pub fn bands(&self) -> &[Band] {
+        &self.bands
+    }
+
+    // retunes a band in place, the filters glide to it so it can be turned while playing
+    pub fn set_band(&mut self, index: usize, band: Band) {
+        if let Some(current) = self.bands.get_mut(index) {
+            *current = band;
+            for filters in self.filters.iter_mut() {
+                filters[index].set_type(band.filter_type());
+                filters[index].set(band.frequency, band.q);
+            }
+        }
+    }
+
+    pub fn process(&mut self, (left, right): (f64, f64)) -> (f64, f64) {
+        let mut output = [left, right];
+        for (filters, sample) in self.filters.iter_mut().zip(output.iter_mut()) {
+            for (filter, band) in filters.iter_mut().zip(self.bands.iter()) {
+                // disabled bands keep running so switching them back on doesn't click
+                let filtered = filter.process(*sample);
+                if band.enabled {
+                    *sample = filtered;
+                }
+            }
+        }
+        (output[0], output[1])
+    }
+
+    // gain in dB of all the enabled bands together at hertz, for plotting the curve
+    pub fn response(&self, hertz: f64) -> f64 {
+        self.filters[0].iter().zip(self.bands.iter())
+            .filter(|(_, band)| band.enabled)
+            .fold(0_f64, |gain, (filter, _)| gain + filter.response(hertz))
+    }
+
+    pub fn reset(&mut self) {
+        self.filters.iter_mut().flatten().for_each(|filter| filter.reset());
+    }
+}
+
+// each band's settings are named after where it is in the list, "band 1 frequency", "band 1 gain" and "band 1 q"
+impl Effect for Equaliser {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        Equaliser::process(self, frame)
+    }
+
+    fn reset(&mut self) {
+        Equaliser::reset(self)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        self.bands.iter().enumerate().flat_map(|(i, band)| vec![
+            (format!("band {} frequency", i + 1), band.frequency),
+            (format!("band {} gain", i + 1), band.gain),
+            (format!("band {} q", i + 1), band.q)
+        ]).collect()
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        let mut words = name.split(' ');
+        let index = match (words.next(), words.next().and_then(|number| number.parse::<usize>().ok())) {
+            (Some("band"), Some(number)) if number >= 1 && number <= self.bands.len() => number - 1,
+            _ => return false
+        };
+        let mut band = self.bands[index];
+        match (words.next(), words.next()) {
+            (Some("frequency"), None) => band.frequency = value,
+            (Some("gain"), None) => band.gain = value,
+            (Some("q"), None) => band.q = value,
+            _ => return false
+        }
+        self.set_band(index, band);
+        true
+    }
+}
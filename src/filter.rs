@@ -0,0 +1,408 @@
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+use serde::{Deserialize, Serialize};
+use crate::envelope::Envelope;
+use crate::instrument::Note;
+use crate::oscillator::SAMPLE_RATE;
+
+// the responses from Robert Bristow-Johnson's audio EQ cookbook
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum FilterType {
+    LowPass,
+    HighPass,
+    BandPass, // 0 dB at the centre frequency
+    Notch,
+    AllPass,
+    Peaking(f64), // gain in dB
+    LowShelf(f64), // gain in dB
+    HighShelf(f64) // gain in dB
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+struct Coefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64
+}
+
+impl Coefficients {
+    fn new(filter_type: FilterType, cutoff: f64, q: f64) -> Self {
+        let w0 = 2_f64 * PI * cutoff / SAMPLE_RATE;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2_f64 * q);
+
+        let (b0, b1, b2, a0, a1, a2) = match filter_type {
+            FilterType::LowPass => ((1_f64 - cos) / 2_f64, 1_f64 - cos, (1_f64 - cos) / 2_f64, 1_f64 + alpha, -2_f64 * cos, 1_f64 - alpha),
+            FilterType::HighPass => ((1_f64 + cos) / 2_f64, -(1_f64 + cos), (1_f64 + cos) / 2_f64, 1_f64 + alpha, -2_f64 * cos, 1_f64 - alpha),
+            FilterType::BandPass => (alpha, 0_f64, -alpha, 1_f64 + alpha, -2_f64 * cos, 1_f64 - alpha),
+            FilterType::Notch => (1_f64, -2_f64 * cos, 1_f64, 1_f64 + alpha, -2_f64 * cos, 1_f64 - alpha),
+            FilterType::AllPass => (1_f64 - alpha, -2_f64 * cos, 1_f64 + alpha, 1_f64 + alpha, -2_f64 * cos, 1_f64 - alpha),
+            FilterType::Peaking(gain) => {
+                let a = 10_f64.powf(gain / 40_f64);
+                (1_f64 + alpha * a, -2_f64 * cos, 1_f64 - alpha * a, 1_f64 + alpha / a, -2_f64 * cos, 1_f64 - alpha / a)
+            },
+            FilterType::LowShelf(gain) => {
+                let a = 10_f64.powf(gain / 40_f64);
+                let beta = 2_f64 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1_f64) - (a - 1_f64) * cos + beta),
+                    2_f64 * a * ((a - 1_f64) - (a + 1_f64) * cos),
+                    a * ((a + 1_f64) - (a - 1_f64) * cos - beta),
+                    (a + 1_f64) + (a - 1_f64) * cos + beta,
+                    -2_f64 * ((a - 1_f64) + (a + 1_f64) * cos),
+                    (a + 1_f64) + (a - 1_f64) * cos - beta
+                )
+            },
+            FilterType::HighShelf(gain) => {
+                let a = 10_f64.powf(gain / 40_f64);
+                let beta = 2_f64 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1_f64) + (a - 1_f64) * cos + beta),
+                    -2_f64 * a * ((a - 1_f64) + (a + 1_f64) * cos),
+                    a * ((a + 1_f64) + (a - 1_f64) * cos - beta),
+                    (a + 1_f64) - (a - 1_f64) * cos + beta,
+                    2_f64 * ((a - 1_f64) - (a + 1_f64) * cos),
+                    (a + 1_f64) - (a - 1_f64) * cos - beta
+                )
+            }
+        };
+
+        Self { b0: b0 / a0, b1: b1 / a0, b2: b2 / a0, a1: a1 / a0, a2: a2 / a0 }
+    }
+
+    // moves a step of the way towards target
+    fn glide(&mut self, target: &Coefficients, smoothing: f64) {
+        self.b0 = target.b0 + (self.b0 - target.b0) * smoothing;
+        self.b1 = target.b1 + (self.b1 - target.b1) * smoothing;
+        self.b2 = target.b2 + (self.b2 - target.b2) * smoothing;
+        self.a1 = target.a1 + (self.a1 - target.a1) * smoothing;
+        self.a2 = target.a2 + (self.a2 - target.a2) * smoothing;
+    }
+}
+
+// one second order section, transposed direct form II
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    s1: f64,
+    s2: f64
+}
+
+impl Biquad {
+    fn process(&mut self, c: &Coefficients, input: f64) -> f64 {
+        let output = c.b0 * input + self.s1;
+        self.s1 = c.b1 * input - c.a1 * output + self.s2;
+        self.s2 = c.b2 * input - c.a2 * output;
+        output
+    }
+}
+
+// a biquad response, repeated over several identical sections for steeper slopes
+// (each one adds 12 dB per octave to a low or high pass)
+#[derive(Clone)]
+pub struct Filter {
+    filter_type: FilterType,
+    cutoff: f64,
+    q: f64,
+    target: Coefficients,
+    current: Coefficients,
+    first: Biquad,
+    more: Vec<Biquad>, // empty for a single section, so those don't allocate
+    smoothing: f64
+}
+
+impl Filter {
+    pub fn new(filter_type: FilterType, cutoff: f64, q: f64, stages: usize) -> Self {
+        let mut filter = Self {
+            filter_type,
+            cutoff: 0_f64,
+            q: 0_f64,
+            target: Coefficients::default(),
+            current: Coefficients::default(),
+            first: Biquad::default(),
+            more: vec![Biquad::default(); stages.max(1) - 1],
+            // coefficients take about 5ms to follow a change, so sweeps don't click
+            smoothing: (-1_f64 / (0.005_f64 * SAMPLE_RATE)).exp()
+        };
+        filter.set(cutoff, q);
+        filter.current = filter.target;
+        filter
+    }
+
+    pub fn filter_type(&self) -> FilterType {
+        self.filter_type
+    }
+
+    pub fn cutoff(&self) -> f64 {
+        self.cutoff
+    }
+
+    pub fn q(&self) -> f64 {
+        self.q
+    }
+
+    pub fn set(&mut self, cutoff: f64, q: f64) {
+        let cutoff = cutoff.clamp(10_f64, 0.49_f64 * SAMPLE_RATE);
+        let q = q.max(0.01_f64);
+        if cutoff != self.cutoff || q != self.q {
+            self.cutoff = cutoff;
+            self.q = q;
+            self.target = Coefficients::new(self.filter_type, cutoff, q);
+        }
+    }
+
+    pub fn set_type(&mut self, filter_type: FilterType) {
+        if filter_type != self.filter_type {
+            self.filter_type = filter_type;
+            self.target = Coefficients::new(filter_type, self.cutoff, self.q);
+        }
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        if self.current != self.target {
+            self.current.glide(&self.target, self.smoothing);
+        }
+        let coefficients = self.current;
+        let output = self.first.process(&coefficients, input);
+        self.more.iter_mut().fold(output, |signal, section| section.process(&coefficients, signal))
+    }
+
+    // forgets everything it has heard, ready for a new sound
+    pub fn reset(&mut self) {
+        self.first = Biquad::default();
+        self.more.iter_mut().for_each(|section| *section = Biquad::default());
+        self.current = self.target;
+    }
+
+    // gain in dB the filter settles on at hertz, for drawing its curve
+    pub fn response(&self, hertz: f64) -> f64 {
+        let c = &self.target;
+        let w = 2_f64 * PI * hertz / SAMPLE_RATE;
+        // |b0 + b1 z^-1 + b2 z^-2| / |1 + a1 z^-1 + a2 z^-2| with z on the unit circle
+        let magnitude = |z0: f64, z1: f64, z2: f64| {
+            let re = z0 + z1 * w.cos() + z2 * (2_f64 * w).cos();
+            let im = -z1 * w.sin() - z2 * (2_f64 * w).sin();
+            (re * re + im * im).sqrt()
+        };
+        let section = magnitude(c.b0, c.b1, c.b2) / magnitude(1_f64, c.a1, c.a2);
+        20_f64 * (1 + self.more.len()) as f64 * section.max(1e-12_f64).log10()
+    }
+}
+
+// Zavalishin's zero delay feedback take on the Moog transistor ladder: four one
+// pole low passes with the last one fed back to the input, saturating where the
+// feedback comes in so it can sing on its own without blowing up
+#[derive(Clone)]
+pub struct Ladder {
+    drive: f64, // gain into the saturation, 1 is barely coloured
+    cutoff: f64,
+    g: f64,
+    k: f64,
+    stages: [f64; 4]
+}
+
+impl Ladder {
+    pub fn new(cutoff: f64, resonance: f64, drive: f64) -> Self {
+        let mut ladder = Self { drive: drive.max(0.01_f64), cutoff: 0_f64, g: 0_f64, k: 0_f64, stages: [0_f64; 4] };
+        ladder.set(cutoff, resonance);
+        ladder
+    }
+
+    // resonance from 0 to 1, where it starts to self-oscillate ; a little beyond pushes it harder
+    pub fn set(&mut self, cutoff: f64, resonance: f64) {
+        let cutoff = cutoff.clamp(10_f64, 0.49_f64 * SAMPLE_RATE);
+        if cutoff != self.cutoff {
+            self.cutoff = cutoff;
+            self.g = (PI * cutoff / SAMPLE_RATE).tan();
+        }
+        self.k = 4_f64 * resonance.clamp(0_f64, 1.2_f64);
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        let g = self.g / (1_f64 + self.g);
+        // what the loop would feed back from the stages' stored state alone
+        let feedback = self.stages.iter().fold(0_f64, |sum, &s| sum * g + s / (1_f64 + self.g));
+        let u = (input - self.k * feedback) / (1_f64 + self.k * g * g * g * g);
+        let u = (self.drive * u).tanh() / self.drive;
+
+        self.stages.iter_mut().fold(u, |x, s| {
+            let v = (x - *s) * g;
+            let y = v + *s;
+            *s = y + v;
+            y
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.stages = [0_f64; 4];
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum SvfMode {
+    LowPass,
+    BandPass,
+    HighPass
+}
+
+pub struct SvfOutputs {
+    pub low: f64,
+    pub band: f64,
+    pub high: f64
+}
+
+// Andrew Simper's trapezoidal state-variable filter, 12 dB per octave with every
+// response available at once
+#[derive(Clone)]
+pub struct StateVariable {
+    mode: SvfMode, // which output process gives
+    cutoff: f64,
+    g: f64,
+    k: f64,
+    ic1eq: f64,
+    ic2eq: f64
+}
+
+impl StateVariable {
+    pub fn new(mode: SvfMode, cutoff: f64, resonance: f64) -> Self {
+        let mut svf = Self { mode, cutoff: 0_f64, g: 0_f64, k: 0_f64, ic1eq: 0_f64, ic2eq: 0_f64 };
+        svf.set(cutoff, resonance);
+        svf
+    }
+
+    // resonance from 0 to 1, stopping just short of self-oscillation
+    pub fn set(&mut self, cutoff: f64, resonance: f64) {
+        let cutoff = cutoff.clamp(10_f64, 0.49_f64 * SAMPLE_RATE);
+        if cutoff != self.cutoff {
+            self.cutoff = cutoff;
+            self.g = (PI * cutoff / SAMPLE_RATE).tan();
+        }
+        self.k = 2_f64 - 2_f64 * resonance.clamp(0_f64, 0.995_f64);
+    }
+
+    pub fn process_all(&mut self, input: f64) -> SvfOutputs {
+        let a1 = 1_f64 / (1_f64 + self.g * (self.g + self.k));
+        let a2 = self.g * a1;
+        let a3 = self.g * a2;
+
+        let v3 = input - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = 2_f64 * v1 - self.ic1eq;
+        self.ic2eq = 2_f64 * v2 - self.ic2eq;
+
+        SvfOutputs { low: v2, band: v1, high: input - self.k * v1 - v2 }
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        let outputs = self.process_all(input);
+        match self.mode {
+            SvfMode::LowPass => outputs.low,
+            SvfMode::BandPass => outputs.band,
+            SvfMode::HighPass => outputs.high
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.ic1eq = 0_f64;
+        self.ic2eq = 0_f64;
+    }
+}
+
+// any of the filters, driven by a cutoff and a resonance from 0 to 1
+#[derive(Clone)]
+pub enum FilterModel {
+    Biquad(Filter),
+    Ladder(Ladder),
+    StateVariable(StateVariable)
+}
+
+impl From<Filter> for FilterModel {
+    fn from(filter: Filter) -> Self {
+        FilterModel::Biquad(filter)
+    }
+}
+
+impl From<Ladder> for FilterModel {
+    fn from(ladder: Ladder) -> Self {
+        FilterModel::Ladder(ladder)
+    }
+}
+
+impl From<StateVariable> for FilterModel {
+    fn from(svf: StateVariable) -> Self {
+        FilterModel::StateVariable(svf)
+    }
+}
+
+impl FilterModel {
+    pub fn set(&mut self, cutoff: f64, resonance: f64) {
+        match self {
+            // a Q from a plain Butterworth 0.707 up to a sharp peak of 20
+            FilterModel::Biquad(filter) => filter.set(cutoff, FRAC_1_SQRT_2 * 28_f64.powf(resonance.clamp(0_f64, 1_f64))),
+            FilterModel::Ladder(ladder) => ladder.set(cutoff, resonance),
+            FilterModel::StateVariable(svf) => svf.set(cutoff, resonance)
+        }
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        match self {
+            FilterModel::Biquad(filter) => filter.process(input),
+            FilterModel::Ladder(ladder) => ladder.process(input),
+            FilterModel::StateVariable(svf) => svf.process(input)
+        }
+    }
+
+    pub fn reset(&mut self) {
+        match self {
+            FilterModel::Biquad(filter) => filter.reset(),
+            FilterModel::Ladder(ladder) => ladder.reset(),
+            FilterModel::StateVariable(svf) => svf.reset()
+        }
+    }
+}
+
+// a filter for every voice of an instrument, with its own envelope sweeping the
+// cutoff and the cutoff following the keyboard
+#[derive(Clone)]
+pub struct VoiceFilter {
+    pub model: FilterModel,
+    pub cutoff: f64,
+    pub resonance: f64,
+    pub envelope: Envelope,
+    pub envelope_amount: f64, // octaves the envelope moves the cutoff at its peak
+    pub key_tracking: f64 // 1 moves the cutoff an octave with every octave away from note 64
+}
+
+impl VoiceFilter {
+    pub fn new(model: impl Into<FilterModel>, cutoff: f64, resonance: f64) -> Self {
+        Self {
+            model: model.into(),
+            cutoff,
+            resonance,
+            envelope: Envelope::default(),
+            envelope_amount: 0_f64,
+            key_tracking: 0_f64
+        }
+    }
+
+    pub fn with_envelope(mut self, envelope: impl Into<Envelope>, amount: f64) -> Self {
+        self.envelope = envelope.into();
+        self.envelope_amount = amount;
+        self
+    }
+
+    pub fn with_key_tracking(mut self, key_tracking: f64) -> Self {
+        self.key_tracking = key_tracking;
+        self
+    }
+
+    // cutoff for the note at time, whose trigger is the filter envelope's own ;
+    // modulation is extra octaves from elsewhere
+    pub fn note_cutoff(&self, time: f64, n: Note, modulation: f64) -> f64 {
+        let octaves = self.envelope_amount * self.envelope.note_amplitude(time, n) +
+            self.key_tracking * (n.id - 64) as f64 / 12_f64 +
+            modulation;
+        self.cutoff * 2_f64.powf(octaves)
+    }
+}
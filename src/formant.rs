@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use crate::envelope::*;
+use crate::filter::*;
+use crate::instrument::*;
+use crate::modulation::Destination;
+use crate::oscillator::*;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum VoiceType {
+    Bass,
+    Tenor,
+    CounterTenor,
+    Alto,
+    Soprano
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Vowel {
+    A,
+    E,
+    I,
+    O,
+    U
+}
+
+impl Vowel {
+    // where the vowel sits when morphing, a is 0 and u is 4
+    pub fn position(&self) -> f64 {
+        match self {
+            Vowel::A => 0_f64,
+            Vowel::E => 1_f64,
+            Vowel::I => 2_f64,
+            Vowel::O => 3_f64,
+            Vowel::U => 4_f64
+        }
+    }
+}
+
+// one resonance of the vocal tract
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Formant {
+    pub frequency: f64,
+    pub gain: f64, // dB
+    pub bandwidth: f64
+}
+
+// frequencies, gains and bandwidths of the first five formants, from the table in the Csound manual
+type FormantTable = ([f64; 5], [f64; 5], [f64; 5]);
+
+fn table(voice_type: VoiceType, vowel: Vowel) -> FormantTable {
+    match (voice_type, vowel) {
+        (VoiceType::Bass, Vowel::A) => ([600., 1040., 2250., 2450., 2750.], [0., -7., -9., -9., -20.], [60., 70., 110., 120., 130.]),
+        (VoiceType::Bass, Vowel::E) => ([400., 1620., 2400., 2800., 3100.], [0., -12., -9., -12., -18.], [40., 80., 100., 120., 120.]),
+        (VoiceType::Bass, Vowel::I) => ([250., 1750., 2600., 3050., 3340.], [0., -30., -16., -22., -28.], [60., 90., 100., 120., 120.]),
+        (VoiceType::Bass, Vowel::O) => ([400., 750., 2400., 2600., 2900.], [0., -11., -21., -20., -40.], [40., 80., 100., 120., 120.]),
+        (VoiceType::Bass, Vowel::U) => ([350., 600., 2400., 2675., 2950.], [0., -20., -32., -28., -36.], [40., 80., 100., 120., 120.]),
+        (VoiceType::Tenor, Vowel::A) => ([650., 1080., 2650., 2900., 3250.], [0., -6., -7., -8., -22.], [80., 90., 120., 130., 140.]),
+        (VoiceType::Tenor, Vowel::E) => ([400., 1700., 2600., 3200., 3580.], [0., -14., -12., -14., -20.], [70., 80., 100., 120., 120.]),
+        (VoiceType::Tenor, Vowel::I) => ([290., 1870., 2800., 3250., 3540.], [0., -15., -18., -20., -30.], [40., 90., 100., 120., 120.]),
+        (VoiceType::Tenor, Vowel::O) => ([400., 800., 2600., 2800., 3000.], [0., -10., -12., -12., -26.], [40., 80., 100., 120., 120.]),
+        (VoiceType::Tenor, Vowel::U) => ([350., 600., 2700., 2900., 3300.], [0., -20., -17., -14., -26.], [40., 60., 100., 120., 120.]),
+        (VoiceType::CounterTenor, Vowel::A) => ([660., 1120., 2750., 3000., 3350.], [0., -6., -23., -24., -38.], [80., 90., 120., 130., 140.]),
+        (VoiceType::CounterTenor, Vowel::E) => ([440., 1800., 2700., 3000., 3300.], [0., -14., -18., -20., -20.], [70., 80., 100., 120., 120.]),
+        (VoiceType::CounterTenor, Vowel::I) => ([270., 1850., 2900., 3350., 3590.], [0., -24., -24., -36., -36.], [40., 90., 100., 120., 120.]),
+        (VoiceType::CounterTenor, Vowel::O) => ([430., 820., 2700., 3000., 3300.], [0., -10., -26., -22., -34.], [40., 80., 100., 120., 120.]),
+        (VoiceType::CounterTenor, Vowel::U) => ([370., 630., 2750., 3000., 3400.], [0., -20., -23., -30., -34.], [40., 60., 100., 120., 120.]),
+        (VoiceType::Alto, Vowel::A) => ([800., 1150., 2800., 3500., 4950.], [0., -4., -20., -36., -60.], [80., 90., 120., 130., 140.]),
+        (VoiceType::Alto, Vowel::E) => ([400., 1600., 2700., 3300., 4950.], [0., -24., -30., -35., -60.], [60., 80., 120., 150., 200.]),
+        (VoiceType::Alto, Vowel::I) => ([350., 1700., 2700., 3700., 4950.], [0., -20., -30., -36., -60.], [50., 100., 120., 150., 200.]),
+        (VoiceType::Alto, Vowel::O) => ([450., 800., 2830., 3500., 4950.], [0., -9., -16., -28., -55.], [70., 80., 100., 130., 135.]),
+        (VoiceType::Alto, Vowel::U) => ([325., 700., 2530., 3500., 4950.], [0., -12., -30., -40., -64.], [50., 60., 170., 180., 200.]),
+        (VoiceType::Soprano, Vowel::A) => ([800., 1150., 2900., 3900., 4950.], [0., -6., -32., -20., -50.], [80., 90., 120., 130., 140.]),
+        (VoiceType::Soprano, Vowel::E) => ([350., 2000., 2800., 3600., 4950.], [0., -20., -15., -40., -56.], [60., 100., 120., 150., 200.]),
+        (VoiceType::Soprano, Vowel::I) => ([270., 2140., 2950., 3900., 4950.], [0., -12., -26., -26., -44.], [60., 90., 100., 120., 120.]),
+        (VoiceType::Soprano, Vowel::O) => ([450., 800., 2830., 3800., 4950.], [0., -11., -22., -22., -50.], [70., 80., 100., 130., 135.]),
+        (VoiceType::Soprano, Vowel::U) => ([325., 700., 2700., 3800., 4950.], [0., -16., -35., -40., -60.], [50., 60., 170., 180., 200.])
+    }
+}
+
+pub fn formants(voice_type: VoiceType, vowel: Vowel) -> [Formant; 5] {
+    let (frequencies, gains, bandwidths) = table(voice_type, vowel);
+    let mut formants = [Formant { frequency: 0_f64, gain: 0_f64, bandwidth: 0_f64 }; 5];
+    for (i, formant) in formants.iter_mut().enumerate() {
+        *formant = Formant { frequency: frequencies[i], gain: gains[i], bandwidth: bandwidths[i] };
+    }
+    formants
+}
+
+// formants part of the way along a, e, i, o, u ; 1.5 is halfway between e and i
+pub fn morph(voice_type: VoiceType, position: f64) -> [Formant; 5] {
+    const VOWELS: [Vowel; 5] = [Vowel::A, Vowel::E, Vowel::I, Vowel::O, Vowel::U];
+    let position = position.clamp(0_f64, 4_f64);
+    let index = (position.floor() as usize).min(3);
+    let fraction = position - index as f64;
+
+    let from = formants(voice_type, VOWELS[index]);
+    let to = formants(voice_type, VOWELS[index + 1]);
+    let mut formants = from;
+    for (i, formant) in formants.iter_mut().enumerate() {
+        // frequencies glide in pitch, gains in dB
+        formant.frequency = from[i].frequency * (to[i].frequency / from[i].frequency).powf(fraction);
+        formant.gain = from[i].gain + (to[i].gain - from[i].gain) * fraction;
+        formant.bandwidth = from[i].bandwidth + (to[i].bandwidth - from[i].bandwidth) * fraction;
+    }
+    formants
+}
+
+// a band pass for each formant, side by side ; the bands are kept inline so
+// a note can make one without allocating
+#[derive(Clone)]
+pub struct FormantFilter {
+    bands: [(Filter, f64); 5]
+}
+
+impl Default for FormantFilter {
+    fn default() -> Self {
+        Self::new(&formants(VoiceType::Tenor, Vowel::A))
+    }
+}
+
+impl FormantFilter {
+    pub fn new(formants: &[Formant; 5]) -> Self {
+        let mut filter = Self {
+            bands: formants.map(|formant| (Filter::new(FilterType::BandPass, formant.frequency, formant.frequency / formant.bandwidth, 1), 0_f64))
+        };
+        filter.set(formants);
+        filter
+    }
+
+    // the bands glide to their new settings rather than jumping
+    pub fn set(&mut self, formants: &[Formant; 5]) {
+        for ((band, gain), formant) in self.bands.iter_mut().zip(formants) {
+            band.set(formant.frequency, formant.frequency / formant.bandwidth);
+            *gain = 10_f64.powf(formant.gain / 20_f64);
+        }
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        self.bands.iter_mut().fold(0_f64, |output, (band, gain)| output + *gain * band.process(input))
+    }
+
+    pub fn reset(&mut self) {
+        self.bands.iter_mut().for_each(|(band, _)| band.reset());
+    }
+}
+
+// a buzzing throat shaped into the vowel for a Synth, which the matrix can morph
+pub struct Vocal(pub VoiceType, pub Vowel);
+
+impl Sound for Vocal {
+    type State = Box<FormantFilter>;
+
+    fn state(&self, _n: Note) -> Box<FormantFilter> {
+        Box::new(FormantFilter::new(&formants(self.0, self.1)))
+    }
+
+    fn sample(&self, m: &Moment, throat: &mut Box<FormantFilter>) -> (f64, f64) {
+        let Vocal(voice_type, vowel) = *self;
+        // a little vibrato and breath make the buzz sound sung
+        let buzz = osc(scale(m.note.id, ScaleType::Default), m.life_time, OscType::AnalogSawWave, 5_f64, 0.003_f64) +
+            0.05_f64 * osc(0_f64, m.life_time, OscType::RandomNoise, 0_f64, 0_f64);
+        throat.set(&morph(voice_type, vowel.position() + m.modulation(Destination::Vowel)));
+        pan(m.amplitude * throat.process(buzz), 0_f64)
+    }
+
+    fn volume(&self) -> f64 {
+        2_f64
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0.08_f64,
+            decay_time: 0.2_f64,
+            release_time: 0.3_f64,
+            sustain_amplitude: 0.9_f64,
+            release_curve: Curve::Exponential,
+            ..Default::default()
+        }.into()
+    }
+}
@@ -0,0 +1,134 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+use crate::envelope::*;
+use crate::instrument::*;
+use crate::modulation::Destination;
+use crate::oscillator::*;
+use crate::sampler::Sample;
+
+// more grains than this at once and new ones are skipped
+const MAX_GRAINS: usize = 256;
+
+#[derive(Clone, Copy)]
+pub struct GrainParams {
+    pub position: f64, // where grains are taken from, 0 is the start of the source and 1 its end
+    pub size: f64, // length of a grain in seconds
+    pub density: f64, // grains started per second
+    pub pitch: f64, // semitones on top of the note
+    pub spray: f64 // random offset added to position, as a fraction of the source
+}
+
+impl Default for GrainParams {
+    fn default() -> Self {
+        Self {
+            position: 0_f64,
+            size: 0.1_f64,
+            density: 20_f64,
+            pitch: 0_f64,
+            spray: 0_f64
+        }
+    }
+}
+
+struct Grain {
+    position: f64, // in source frames
+    speed: f64, // source frames per output sample
+    age: usize,
+    length: usize
+}
+
+// overlapping windowed slices of a source, the parameters are read every
+// sample so they can be modulated while the note plays
+pub struct Granulator {
+    source: Arc<Sample>,
+    grains: Vec<Grain>,
+    next_grain: f64,
+    seed: u64,
+    count: u64
+}
+
+impl Granulator {
+    pub fn new(source: Arc<Sample>, seed: u64) -> Self {
+        Self {
+            source,
+            grains: Vec::with_capacity(MAX_GRAINS),
+            next_grain: 1_f64, // the first grain starts straight away
+            seed,
+            count: 0
+        }
+    }
+
+    fn random(&mut self) -> f64 {
+        self.count += 1;
+        seeded_random(self.seed.wrapping_mul(31).wrapping_add(self.count))
+    }
+
+    // ratio is how much faster than recorded the source plays for this note
+    pub fn tick(&mut self, params: GrainParams, ratio: f64) -> f64 {
+        let frames = self.source.frames() as f64;
+
+        self.next_grain += params.density.max(0_f64) / SAMPLE_RATE;
+        while self.next_grain >= 1_f64 {
+            self.next_grain -= 1_f64;
+            let length = (params.size * SAMPLE_RATE) as usize;
+            if self.grains.len() < MAX_GRAINS && length > 1 {
+                let spray = params.spray * (2_f64 * self.random() - 1_f64);
+                self.grains.push(Grain {
+                    position: (params.position + spray).clamp(0_f64, 1_f64) * frames,
+                    speed: ratio * 2_f64.powf(params.pitch / 12_f64) * self.source.sample_rate / SAMPLE_RATE,
+                    age: 0,
+                    length
+                });
+            }
+        }
+
+        let source = &self.source;
+        let output = self.grains.iter_mut().fold(0_f64, |output, grain| {
+            // Hann window, so overlapping grains fade in and out of each other
+            let window = 0.5_f64 - 0.5_f64 * (2_f64 * PI * grain.age as f64 / grain.length as f64).cos();
+            let value = source.value(grain.position);
+            grain.position += grain.speed;
+            grain.age += 1;
+            output + window * value
+        });
+        self.grains.retain(|grain| grain.age < grain.length);
+
+        // grains are uncorrelated so they add up in power, half the window area on average
+        let overlap = params.density * params.size * 0.5_f64;
+        output / overlap.max(1_f64).sqrt()
+    }
+}
+
+// a source played as grains by a Synth, the params are where the matrix starts from
+pub struct Granular(pub Arc<Sample>, pub GrainParams);
+
+impl Sound for Granular {
+    type State = Granulator;
+
+    fn state(&self, n: Note) -> Granulator {
+        Granulator::new(self.0.clone(), n.seed())
+    }
+
+    fn sample(&self, m: &Moment, granulator: &mut Granulator) -> (f64, f64) {
+        let Granular(source, params) = self;
+        let params = GrainParams {
+            position: params.position + m.modulation(Destination::GrainPosition),
+            size: f64::max(params.size + m.modulation(Destination::GrainSize), 0_f64),
+            density: f64::max(params.density + m.modulation(Destination::GrainDensity), 0_f64),
+            pitch: params.pitch + m.modulation(Destination::GrainPitch),
+            spray: f64::max(params.spray + m.modulation(Destination::GrainSpray), 0_f64)
+        };
+        let ratio = 2_f64.powf((m.note.id - source.root_key.unwrap_or(64)) as f64 / 12_f64);
+        pan(m.amplitude * granulator.tick(params, ratio), 0_f64)
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0.3_f64,
+            decay_time: 0_f64,
+            release_time: 1_f64,
+            sustain_amplitude: 1_f64,
+            ..Default::default()
+        }.into()
+    }
+}
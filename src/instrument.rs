@@ -0,0 +1,768 @@
+use std::any::Any;
+use std::f64::consts::SQRT_2;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::envelope::*;
+use crate::filter::*;
+use crate::lfo::*;
+use crate::modulation::*;
+use crate::oscillator::*;
+
+// a sounding note and whatever its instrument needs to remember about it from one
+// sample to the next. It belongs to whoever plays the note, so the audio thread can
+// change it without locking, and it is made when the key goes down so nothing is
+// allocated while rendering
+pub struct Voice {
+    pub note: Note,
+    state: Box<dyn Any + Send>
+}
+
+impl Voice {
+    pub fn new(note: Note, state: impl Any + Send) -> Self {
+        Self { note, state: Box::new(state) }
+    }
+
+    // the state the instrument made the voice with, None if it was made by another kind of instrument
+    pub fn state<S: Any>(&self) -> Option<&S> {
+        self.state.downcast_ref()
+    }
+
+    pub fn state_mut<S: Any>(&mut self) -> Option<&mut S> {
+        self.state.downcast_mut()
+    }
+}
+
+// anything that plays notes, the built in sounds are all Synths and other crates can
+// bring their own ; instruments are shared between the UI thread, which starts and
+// stops notes, and the audio thread, which renders them
+pub trait Instrument: Send + Sync {
+    // a key has been pressed, the voice is called on once per sample from then on
+    fn note_on(&self, id: i32, time: f64) -> Voice {
+        Voice::new(Note { id, on: time, pressed: time, active: true, ..Default::default() }, ())
+    }
+
+    // the voice at time in stereo
+    fn render(&self, time: f64, voice: &mut Voice) -> (f64, f64);
+
+    // silent for good, the voice doesn't need to be rendered any more ; by default
+    // as soon as the key is let go
+    fn finished(&self, _time: f64, voice: &Voice) -> bool {
+        voice.note.off > voice.note.on
+    }
+
+    // the key of a note that is still sounding has been pressed again
+    fn retrigger(&self, voice: &mut Voice, time: f64) {
+        voice.note.on = time;
+    }
+
+    // the key has been let go
+    fn note_off(&self, voice: &mut Voice, time: f64) {
+        if voice.note.off < voice.note.on {
+            voice.note.off = time;
+        }
+    }
+
+    // names and current values of the settings that can be changed while playing
+    fn parameters(&self) -> Vec<(String, f64)> {
+        Vec::new()
+    }
+
+    // false when the instrument has no parameter called name
+    fn set_parameter(&self, _name: &str, _value: f64) -> bool {
+        false
+    }
+
+    fn parameter(&self, name: &str) -> Option<f64> {
+        self.parameters().into_iter().find(|(parameter, _)| parameter == name).map(|(_, value)| value)
+    }
+}
+
+// where the notes of a Synth come from, before its envelope, filter, modulation and
+// panning are applied. The built in ones are below and next to what they are made
+// of, other crates can bring their own and get the rest for free
+pub trait Sound: Send + Sync {
+    // whatever a note has to remember between samples, () if it is worked out from the time alone
+    type State: Send + 'static;
+
+    fn state(&self, n: Note) -> Self::State;
+
+    // one frame of the note, before the Synth's volume ; panned the way pan and
+    // osc_unison do it, so a mono sound is pan(sample, 0) and the Synth's pan moves it from there
+    fn sample(&self, moment: &Moment, state: &mut Self::State) -> (f64, f64);
+
+    // by default once the amplitude envelope has released
+    fn finished(&self, time: f64, n: Note, envelope: &Envelope, _state: &Self::State) -> bool {
+        envelope.finished(time, n)
+    }
+
+    // what a Synth made from it starts out with
+    fn volume(&self) -> f64 {
+        1_f64
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR::default().into()
+    }
+
+    fn filter(&self) -> Option<VoiceFilter> {
+        None
+    }
+
+    fn matrix(&self) -> ModMatrix {
+        ModMatrix::default()
+    }
+}
+
+// what a Synth has worked out for a note at one sample, for its sound to use
+pub struct Moment<'a> {
+    pub time: f64,
+    pub note: Note,
+    pub life_time: f64, // seconds since the key first went down, running faster or slower with pitch modulation
+    pub amplitude: f64, // level of the amplitude envelope
+    pub pulse_width: f64, // 0.5 for a square unless modulated
+    pub osc_mix: f64, // level of the layers on top of the first oscillator
+    pub envelope: &'a Envelope,
+    matrix: &'a ModMatrix,
+    triggers: &'a [Trigger],
+    mod_wheel: f64,
+    tempo: f64
+}
+
+impl Moment<'_> {
+    // the key is still down
+    pub fn held(&self) -> bool {
+        self.note.on > self.note.off
+    }
+
+    // sum of everything the matrix routes to destination
+    pub fn modulation(&self, destination: Destination) -> f64 {
+        self.matrix.value(destination, self.time, self.note, self.triggers, self.mod_wheel, self.tempo)
+    }
+}
+
+// an f64 the UI thread can change while the audio thread reads it
+struct Shared(AtomicU64);
+
+impl Shared {
+    fn new(value: f64) -> Self {
+        Self(AtomicU64::new(value.to_bits()))
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+// a sound shaped by an envelope, a filter and a modulation matrix, then placed in
+// the stereo field
+pub struct Synth<S: Sound> {
+    sound: S,
+    volume: Shared,
+    envelope: Envelope,
+    matrix: ModMatrix,
+    mod_wheel: Shared,
+    tempo: Shared, // beats per minute, for synced LFOs
+    filter: Option<VoiceFilter>, // its model is copied for every note that is played
+    pan: Shared,
+    pan_law: PanLaw,
+    pan_spread: Shared,
+    pan_centre: Shared // note that pan spread leaves in the middle
+}
+
+impl<S: Sound> Synth<S> {
+    pub fn new(sound: S) -> Self {
+        Self {
+            volume: Shared::new(sound.volume()),
+            envelope: sound.envelope(),
+            matrix: sound.matrix(),
+            mod_wheel: Shared::new(0_f64),
+            tempo: Shared::new(120_f64),
+            filter: sound.filter(),
+            pan: Shared::new(0_f64),
+            pan_law: PanLaw::ConstantPower,
+            pan_spread: Shared::new(0_f64),
+            pan_centre: Shared::new(64_f64),
+            sound
+        }
+    }
+
+    pub fn with_matrix(mut self, matrix: ModMatrix) -> Self {
+        self.matrix = matrix;
+        self
+    }
+
+    // adds an LFO to the matrix and routes it to one of the instrument's parameters
+    pub fn with_lfo(mut self, lfo: Lfo, destination: Destination, amount: f64) -> Self {
+        self.matrix.lfos.push(lfo);
+        self.matrix.route(Source::Lfo(self.matrix.lfos.len() - 1), destination, amount);
+        self
+    }
+
+    // replaces the amplitude envelope the sound comes with
+    pub fn with_envelope(mut self, envelope: impl Into<Envelope>) -> Self {
+        self.envelope = envelope.into();
+        self
+    }
+
+    // every note goes through its own copy of filter
+    pub fn with_filter(mut self, filter: VoiceFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn with_volume(self, volume: f64) -> Self {
+        self.volume.set(volume);
+        self
+    }
+
+    // -1 is hard left, 1 hard right
+    pub fn with_pan(self, pan: f64) -> Self {
+        self.pan.set(pan);
+        self
+    }
+
+    pub fn with_pan_law(mut self, pan_law: PanLaw) -> Self {
+        self.pan_law = pan_law;
+        self
+    }
+
+    // spreads chords across the stereo field, low notes to the left and high ones
+    // to the right of centre, a note that can fall between two keys ; at 1 two
+    // octaves either side of it are hard left and right
+    pub fn with_pan_spread(self, pan_spread: f64, centre: f64) -> Self {
+        self.pan_spread.set(pan_spread);
+        self.pan_centre.set(centre);
+        self
+    }
+
+    pub fn with_retrigger_mode(mut self, retrigger_mode: RetriggerMode) -> Self {
+        self.envelope.set_retrigger_mode(retrigger_mode);
+        self
+    }
+
+    pub fn sound(&self) -> &S {
+        &self.sound
+    }
+
+    pub fn matrix(&self) -> &ModMatrix {
+        &self.matrix
+    }
+
+    pub fn set_mod_wheel(&self, value: f64) {
+        self.mod_wheel.set(value.clamp(0_f64, 1_f64));
+    }
+
+    // beats per minute of the song the instrument plays in
+    pub fn with_tempo(self, tempo: f64) -> Self {
+        self.set_tempo(tempo);
+        self
+    }
+
+    // synced LFOs follow it straight away, also for notes that are already playing
+    pub fn set_tempo(&self, tempo: f64) {
+        self.tempo.set(tempo.max(1_f64));
+    }
+
+    // sum of everything the matrix routes to destination
+    fn modulation(&self, destination: Destination, time: f64, n: Note, triggers: &[Trigger]) -> f64 {
+        self.matrix.value(destination, time, n, triggers, self.mod_wheel.get(), self.tempo.get())
+    }
+
+    fn voice(&self, time: f64, n: Note, state: &mut SynthState<S::State>) -> (f64, f64) {
+        let (mod_wheel, tempo) = (self.mod_wheel.get(), self.tempo.get());
+        let triggers = &state.triggers;
+        let modulation = |destination| self.matrix.value(destination, time, n, triggers, mod_wheel, tempo);
+        // pitch modulation speeds up or slows down the note's own clock, so every
+        // oscillator follows it without jumping in phase ; neither clock starts again
+        // when the note is retriggered, only the envelopes do
+        let life_time = if self.matrix.modulates(Destination::Pitch) {
+            let bend = 2_f64.powf(modulation(Destination::Pitch) / 12_f64);
+            state.clock += bend / SAMPLE_RATE;
+            state.clock
+        } else {
+            time - n.pressed
+        };
+        let moment = Moment {
+            time,
+            note: n,
+            life_time,
+            amplitude: self.envelope.note_amplitude(time, n),
+            pulse_width: 0.5_f64 + 0.5_f64 * modulation(Destination::PulseWidth),
+            // the first oscillator of every sound stays put, osc mix scales the layers on top of it
+            osc_mix: f64::max(1_f64 + modulation(Destination::OscMix), 0_f64),
+            envelope: &self.envelope,
+            matrix: &self.matrix,
+            triggers,
+            mod_wheel,
+            tempo
+        };
+        let (left, right) = self.sound.sample(&moment, &mut state.sound);
+        let volume = self.volume.get();
+        (left * volume, right * volume)
+    }
+
+    fn filtered_sound(&self, time: f64, n: Note, state: &mut SynthState<S::State>) -> (f64, f64) {
+        let (left, right) = self.voice(time, n, state);
+        let triggers = &state.triggers;
+        let (left, right) = match (&self.filter, &mut state.filter) {
+            (Some(filter), Some([left_model, right_model])) => {
+                let cutoff = filter.note_cutoff(time, n.with_trigger(state.filter_trigger), self.modulation(Destination::Cutoff, time, n, triggers));
+                let resonance = f64::max(filter.resonance + self.modulation(Destination::Resonance, time, n, triggers), 0_f64);
+                left_model.set(cutoff, resonance);
+                right_model.set(cutoff, resonance);
+                (left_model.process(left), right_model.process(right))
+            },
+            _ => (left, right)
+        };
+        let tremolo = f64::max(1_f64 + self.modulation(Destination::Amplitude, time, n, triggers), 0_f64);
+        (left * tremolo, right * tremolo)
+    }
+
+    // the note placed in the stereo field by the instrument's pan, the note's own,
+    // the key spread and whatever the matrix routes to pan ; a centred sound is panned
+    // by the pan law and a wide one is balanced the same way
+    fn stereo_sound(&self, time: f64, n: Note, state: &mut SynthState<S::State>) -> (f64, f64) {
+        let (left, right) = self.filtered_sound(time, n, state);
+        let pan = self.pan.get() + n.pan +
+            self.pan_spread.get() * (n.id as f64 - self.pan_centre.get()) / 24_f64 +
+            self.modulation(Destination::Pan, time, n, &state.triggers);
+        let (left_gain, right_gain) = self.pan_law.pan(SQRT_2, pan);
+        (left * left_gain, right * right_gain)
+    }
+}
+
+struct SynthState<T> {
+    sound: T,
+    clock: f64, // seconds the note has played for, running faster or slower with pitch modulation
+    filter: Option<[FilterModel; 2]>, // one for each side
+    filter_trigger: Trigger, // where the filter envelope picks up from
+    triggers: Vec<Trigger> // the same for each of the matrix's envelopes
+}
+
+impl<S: Sound> Instrument for Synth<S> {
+    fn note_on(&self, id: i32, time: f64) -> Voice {
+        let note = Note { id, on: time, pressed: time, active: true, ..Default::default() };
+        Voice::new(note, SynthState {
+            sound: self.sound.state(note),
+            clock: 0_f64,
+            filter: self.filter.as_ref().map(|filter| [filter.model.clone(), filter.model.clone()]),
+            filter_trigger: Trigger::default(),
+            triggers: vec![Trigger::default(); self.matrix.envelopes.len()]
+        })
+    }
+
+    fn render(&self, time: f64, voice: &mut Voice) -> (f64, f64) {
+        let n = voice.note;
+        match voice.state_mut::<SynthState<S::State>>() {
+            Some(state) => self.stereo_sound(time, n, state),
+            None => (0_f64, 0_f64)
+        }
+    }
+
+    fn finished(&self, time: f64, voice: &Voice) -> bool {
+        match voice.state::<SynthState<S::State>>() {
+            Some(state) => self.sound.finished(time, voice.note, &self.envelope, &state.sound),
+            None => true
+        }
+    }
+
+    // each envelope decides whether it starts again from where it is or from silence
+    fn retrigger(&self, voice: &mut Voice, time: f64) {
+        let n = voice.note;
+        if let Some(state) = voice.state_mut::<SynthState<S::State>>() {
+            if let Some(filter) = &self.filter {
+                state.filter_trigger = filter.envelope.trigger(time, n.with_trigger(state.filter_trigger));
+            }
+            for (envelope, trigger) in self.matrix.envelopes.iter().zip(state.triggers.iter_mut()) {
+                *trigger = envelope.trigger(time, n.with_trigger(*trigger));
+            }
+        }
+        self.envelope.retrigger(&mut voice.note, time);
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            ("volume".to_string(), self.volume.get()),
+            ("pan".to_string(), self.pan.get()),
+            ("pan spread".to_string(), self.pan_spread.get()),
+            ("pan centre".to_string(), self.pan_centre.get()),
+            ("mod wheel".to_string(), self.mod_wheel.get()),
+            ("tempo".to_string(), self.tempo.get())
+        ]
+    }
+
+    fn set_parameter(&self, name: &str, value: f64) -> bool {
+        match name {
+            "volume" => self.volume.set(value.max(0_f64)),
+            "pan" => self.pan.set(value.clamp(-1_f64, 1_f64)),
+            "pan spread" => self.pan_spread.set(value),
+            "pan centre" => self.pan_centre.set(value),
+            "mod wheel" => self.set_mod_wheel(value),
+            "tempo" => self.set_tempo(value),
+            _ => return false
+        }
+        true
+    }
+}
+
+// analog style lead, a saw an octave down under square layers, through a ladder filter
+pub struct Harmonica;
+
+impl Sound for Harmonica {
+    type State = ();
+
+    fn state(&self, _n: Note) {}
+
+    fn sample(&self, m: &Moment, _state: &mut ()) -> (f64, f64) {
+        let (id, life_time) = (m.note.id, m.life_time);
+        pan(m.amplitude * (
+            1_f64 * osc(scale(id - 12, ScaleType::Default), -life_time, OscType::AnalogSawWave, 5_f64, 0.001_f64) + m.osc_mix * (
+                1_f64 * osc(scale(id, ScaleType::Default), life_time, OscType::PulseWave(m.pulse_width), 5_f64, 0.001_f64) +
+                0.5_f64 * osc(scale(id + 12, ScaleType::Default), life_time, OscType::PulseWave(m.pulse_width), 0_f64, 0_f64) +
+                0.05_f64 * osc(scale(id + 24, ScaleType::Default), life_time, OscType::RandomNoise, 0_f64, 0_f64)
+            )
+        ), 0_f64)
+    }
+
+    fn volume(&self) -> f64 {
+        0.3_f64
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0_f64,
+            decay_time: 1_f64,
+            release_time: 0.1_f64,
+            sustain_amplitude: 0.95_f64,
+            ..Default::default()
+        }.into()
+    }
+
+    // the saw and square layers go through a ladder like an old analog patch
+    fn filter(&self) -> Option<VoiceFilter> {
+        Some(VoiceFilter::new(Ladder::new(800_f64, 0.35_f64, 1.5_f64), 800_f64, 0.35_f64)
+            .with_envelope(EnvelopeADSR {
+                attack_time: 0.005_f64,
+                decay_time: 0.6_f64,
+                release_time: 0.3_f64,
+                sustain_amplitude: 0.4_f64,
+                decay_curve: Curve::Exponential,
+                release_curve: Curve::Exponential,
+                ..Default::default()
+            }, 3_f64)
+            .with_key_tracking(0.5_f64))
+    }
+}
+
+pub struct Bell;
+
+impl Sound for Bell {
+    type State = ();
+
+    fn state(&self, _n: Note) {}
+
+    fn sample(&self, m: &Moment, _state: &mut ()) -> (f64, f64) {
+        let (id, life_time) = (m.note.id, m.life_time);
+        pan(m.amplitude * (
+            1_f64 * osc(scale(id + 12, ScaleType::Default), life_time, OscType::SineWave, 5_f64, 0.001_f64) + m.osc_mix * (
+                0.5_f64 * osc(scale(id + 24, ScaleType::Default), life_time, OscType::SineWave, 0_f64, 0_f64) +
+                0.25_f64 * osc(scale(id + 36, ScaleType::Default), life_time, OscType::SineWave, 0_f64, 0_f64)
+            )
+        ), 0_f64)
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0.01_f64,
+            decay_time: 1_f64,
+            release_time: 1_f64,
+            sustain_amplitude: 0_f64,
+            decay_curve: Curve::Exponential,
+            release_curve: Curve::Exponential,
+            ..Default::default()
+        }.into()
+    }
+}
+
+// a bell that keeps ringing while the key is held, on an 8 bit style pulse
+pub struct Bell8;
+
+impl Sound for Bell8 {
+    type State = ();
+
+    fn state(&self, _n: Note) {}
+
+    fn sample(&self, m: &Moment, _state: &mut ()) -> (f64, f64) {
+        let (id, life_time) = (m.note.id, m.life_time);
+        pan(m.amplitude * (
+            1_f64 * osc(scale(id, ScaleType::Default), life_time, OscType::PulseWave(m.pulse_width), 5_f64, 0.001_f64) + m.osc_mix * (
+                0.5_f64 * osc(scale(id + 12, ScaleType::Default), life_time, OscType::SineWave, 0_f64, 0_f64) +
+                0.25_f64 * osc(scale(id + 24, ScaleType::Default), life_time, OscType::SineWave, 0_f64, 0_f64)
+            )
+        ), 0_f64)
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0.01_f64,
+            decay_time: 0.5_f64,
+            release_time: 1_f64,
+            sustain_amplitude: 0.8_f64,
+            decay_curve: Curve::Exponential,
+            release_curve: Curve::Exponential,
+            ..Default::default()
+        }.into()
+    }
+}
+
+// detuned unison saws with a sine underneath
+pub struct Supersaw;
+
+impl Sound for Supersaw {
+    type State = ();
+
+    fn state(&self, _n: Note) {}
+
+    fn sample(&self, m: &Moment, _state: &mut ()) -> (f64, f64) {
+        let (id, life_time, seed) = (m.note.id, m.life_time, m.note.seed());
+        // the unison stacks keep their spread, the sine stays in the middle
+        let lead = osc_unison(scale(id, ScaleType::Default), life_time, OscType::DigitalSawWave, 0_f64, 0_f64, Unison { voices: 7, detune: 40_f64, spread: 1_f64 }, seed);
+        let octave = osc_unison(scale(id + 12, ScaleType::Default), life_time, OscType::DigitalSawWave, 0_f64, 0_f64, Unison { voices: 5, detune: 25_f64, spread: 0.6_f64 }, seed.wrapping_add(1));
+        let sub = pan(osc(scale(id - 12, ScaleType::Default), life_time, OscType::SineWave, 0_f64, 0_f64), 0_f64);
+        let mix = |lead: f64, octave: f64, sub: f64| m.amplitude * (1_f64 * lead + m.osc_mix * (0.5_f64 * octave + 0.3_f64 * sub));
+        (mix(lead.0, octave.0, sub.0), mix(lead.1, octave.1, sub.1))
+    }
+
+    fn volume(&self) -> f64 {
+        0.5_f64
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0.05_f64,
+            decay_time: 0.3_f64,
+            release_time: 0.4_f64,
+            sustain_amplitude: 0.8_f64,
+            ..Default::default()
+        }.into()
+    }
+}
+
+pub struct SyncLead;
+
+impl Sound for SyncLead {
+    type State = ();
+
+    fn state(&self, _n: Note) {}
+
+    fn sample(&self, m: &Moment, _state: &mut ()) -> (f64, f64) {
+        let (id, life_time) = (m.note.id, m.life_time);
+        // the slave sweeps down towards the master, which is what gives the lead its growl
+        let sweep = 1.5_f64 + 2_f64 * (-life_time * 3_f64).exp();
+        pan(m.amplitude * (
+            1_f64 * osc_pair(scale(id, ScaleType::Default), OscType::SineWave, scale(id, ScaleType::Default) * sweep, OscType::DigitalSawWave, life_time, OscPairMode::HardSync) + m.osc_mix * (
+                0.3_f64 * osc(scale(id - 12, ScaleType::Default), life_time, OscType::SquareWave, 0_f64, 0_f64)
+            )
+        ), 0_f64)
+    }
+
+    fn volume(&self) -> f64 {
+        0.5_f64
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0.01_f64,
+            decay_time: 0.2_f64,
+            release_time: 0.2_f64,
+            sustain_amplitude: 0.9_f64,
+            ..Default::default()
+        }.into()
+    }
+}
+
+// ring and amplitude modulated sines, inharmonic like struck metal
+pub struct MetalBell;
+
+impl Sound for MetalBell {
+    type State = ();
+
+    fn state(&self, _n: Note) {}
+
+    fn sample(&self, m: &Moment, _state: &mut ()) -> (f64, f64) {
+        let (id, life_time) = (m.note.id, m.life_time);
+        pan(m.amplitude * (
+            0.7_f64 * osc_pair(scale(id, ScaleType::Default), OscType::SineWave, scale(id, ScaleType::Default) * 2.76_f64, OscType::SineWave, life_time, OscPairMode::RingModulation) + m.osc_mix * (
+                0.3_f64 * osc_pair(scale(id + 12, ScaleType::Default) * 1.41_f64, OscType::SineWave, scale(id + 12, ScaleType::Default), OscType::SineWave, life_time, OscPairMode::AmplitudeModulation(0.8_f64))
+            )
+        ), 0_f64)
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0.005_f64,
+            decay_time: 2_f64,
+            release_time: 1.5_f64,
+            sustain_amplitude: 0_f64,
+            decay_curve: Curve::Exponential,
+            release_curve: Curve::Exponential,
+            ..Default::default()
+        }.into()
+    }
+}
+
+// the drums play the same note whatever key they are given and are done after a fixed time
+pub struct DrumKick;
+
+impl Sound for DrumKick {
+    type State = ();
+
+    fn state(&self, _n: Note) {}
+
+    fn sample(&self, m: &Moment, _state: &mut ()) -> (f64, f64) {
+        pan(m.amplitude * (
+            0.99_f64 * osc(scale(28, ScaleType::Default), m.life_time, OscType::SineWave, 1_f64, 1_f64) + m.osc_mix * (
+                0.01_f64 * osc(0_f64, m.life_time, OscType::RandomNoise, 0_f64, 0_f64)
+            )
+        ), 0_f64)
+    }
+
+    fn finished(&self, time: f64, n: Note, _envelope: &Envelope, _state: &()) -> bool {
+        time - n.on >= 1.5_f64
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0.01_f64,
+            decay_time: 0.15_f64,
+            release_time: 0_f64,
+            sustain_amplitude: 0_f64,
+            decay_curve: Curve::Exponential,
+            release_curve: Curve::Exponential,
+            ..Default::default()
+        }.into()
+    }
+}
+
+pub struct DrumSnare;
+
+impl Sound for DrumSnare {
+    type State = ();
+
+    fn state(&self, _n: Note) {}
+
+    fn sample(&self, m: &Moment, _state: &mut ()) -> (f64, f64) {
+        pan(m.amplitude * (
+            0.5_f64 * osc(scale(m.note.id - 24, ScaleType::Default), m.life_time, OscType::SineWave, 0.5_f64, 1_f64) + m.osc_mix * (
+                0.5_f64 * osc(0_f64, m.life_time, OscType::RandomNoise, 0_f64, 0_f64)
+            )
+        ), 0_f64)
+    }
+
+    fn finished(&self, time: f64, n: Note, _envelope: &Envelope, _state: &()) -> bool {
+        time - n.on >= 1_f64
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0_f64,
+            decay_time: 0.2_f64,
+            release_time: 0_f64,
+            sustain_amplitude: 0_f64,
+            decay_curve: Curve::Exponential,
+            release_curve: Curve::Exponential,
+            ..Default::default()
+        }.into()
+    }
+}
+
+pub struct DrumHiHat;
+
+impl Sound for DrumHiHat {
+    type State = ();
+
+    fn state(&self, _n: Note) {}
+
+    fn sample(&self, m: &Moment, _state: &mut ()) -> (f64, f64) {
+        pan(m.amplitude * (
+            0.1_f64 * osc(scale(m.note.id - 12, ScaleType::Default), m.life_time, OscType::SquareWave, 1.5_f64, 1_f64) + m.osc_mix * (
+                0.9_f64 * osc(0_f64, m.life_time, OscType::RandomNoise, 0_f64, 0_f64)
+            )
+        ), 0_f64)
+    }
+
+    fn finished(&self, time: f64, n: Note, _envelope: &Envelope, _state: &()) -> bool {
+        time - n.on >= 1_f64
+    }
+
+    fn volume(&self) -> f64 {
+        0.5_f64
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0.01_f64,
+            decay_time: 0.05_f64,
+            release_time: 0_f64,
+            sustain_amplitude: 0_f64,
+            decay_curve: Curve::Exponential,
+            release_curve: Curve::Exponential,
+            ..Default::default()
+        }.into()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Note {
+    pub id: i32,
+    pub on: f64,
+    pub off: f64,
+    pub velocity: f64,
+    pub aftertouch: f64,
+    pub pressed: f64, // when the key first went down, a retrigger moves on but not this
+    pub trigger: Trigger, // where the amplitude envelope picks up from
+    pub pan: f64, // on top of the instrument's own, -1 is hard left
+    pub active: bool
+}
+
+impl Default for Note {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            on: 0_f64,
+            off: 0_f64,
+            velocity: 1_f64,
+            aftertouch: 0_f64,
+            pressed: 0_f64,
+            trigger: Trigger::default(),
+            pan: 0_f64,
+            active: false
+        }
+    }
+}
+
+impl Note {
+    // identifies this particular key press, used to seed per note randomness ; it
+    // stays the same when the note is retriggered so nothing random jumps
+    pub fn seed(&self) -> u64 {
+        (self.id as u64) ^ self.pressed.to_bits()
+    }
+
+    // the note as an envelope other than the amplitude's sees it, picking up from its own trigger
+    pub fn with_trigger(self, trigger: Trigger) -> Note {
+        Note { trigger, ..self }
+    }
+}
+
+pub enum ScaleType {
+    Default
+}
+
+pub fn scale(note_id: i32, scale_type: ScaleType) -> f64 {
+    match scale_type {
+        ScaleType::Default => 8_f64 * 2_f64.powf(1_f64 / 12_f64).powi(note_id)
+    }
+}
@@ -0,0 +1,85 @@
+use std::f64::consts::PI;
+use serde::{Deserialize, Serialize};
+use crate::instrument::Note;
+use crate::oscillator::seeded_random;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    SampleAndHold // a new random value every cycle
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum LfoRate {
+    Hertz(f64),
+    Synced { beats: f64 } // one cycle every so many beats, follows the instrument's tempo as it changes
+}
+
+impl LfoRate {
+    // tempo in beats per minute
+    pub fn hertz(&self, tempo: f64) -> f64 {
+        match *self {
+            LfoRate::Hertz(hertz) => hertz,
+            LfoRate::Synced { beats } => tempo / 60_f64 / beats
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Lfo {
+    pub shape: LfoShape,
+    pub rate: LfoRate,
+    pub phase: f64, // offset in cycles, 0.25 starts a sine at its top
+    pub delay: f64, // seconds after the note starts before the LFO kicks in
+    pub fade_in: f64, // seconds it then takes to reach its full depth
+    pub retrigger: bool // restart with every note instead of running freely
+}
+
+impl Default for Lfo {
+    fn default() -> Self {
+        Self {
+            shape: LfoShape::Sine,
+            rate: LfoRate::Hertz(5_f64),
+            phase: 0_f64,
+            delay: 0_f64,
+            fade_in: 0_f64,
+            retrigger: false
+        }
+    }
+}
+
+impl Lfo {
+    // between -1 and 1
+    pub fn value(&self, time: f64, n: Note, tempo: f64) -> f64 {
+        // a retriggered note carries on where it was, like the oscillators
+        let life_time = time - n.pressed;
+        let phase = self.rate.hertz(tempo) * if self.retrigger { life_time } else { time } + self.phase;
+        let cycle = phase - phase.floor();
+
+        let value = match self.shape {
+            LfoShape::Sine => (2_f64 * PI * phase).sin(),
+            LfoShape::Triangle => 1_f64 - 4_f64 * ((cycle + 0.25_f64) % 1_f64 - 0.5_f64).abs(),
+            LfoShape::Saw => 2_f64 * ((cycle + 0.5_f64) % 1_f64) - 1_f64,
+            LfoShape::Square => if cycle < 0.5_f64 { 1_f64 } else { -1_f64 },
+            LfoShape::SampleAndHold => {
+                // a free running LFO holds the same values for every note
+                let seed = if self.retrigger { n.seed() } else { 0 };
+                2_f64 * seeded_random(seed ^ phase.floor() as i64 as u64) - 1_f64
+            }
+        };
+
+        let fade = if life_time < self.delay {
+            0_f64
+        } else if self.fade_in > 0_f64 {
+            f64::min((life_time - self.delay) / self.fade_in, 1_f64)
+        } else {
+            1_f64
+        };
+
+        value * fade
+    }
+}
@@ -0,0 +1,18 @@
+pub mod oscillator;
+pub mod envelope;
+pub mod instrument;
+pub mod physical;
+pub mod sampler;
+pub mod granular;
+pub mod lfo;
+pub mod modulation;
+pub mod filter;
+pub mod formant;
+pub mod delay;
+pub mod reverb;
+pub mod chorus;
+pub mod distortion;
+pub mod dynamics;
+pub mod equaliser;
+pub mod effect;
+pub mod patch;
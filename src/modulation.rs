@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use crate::envelope::{Envelope, Trigger};
+use crate::instrument::Note;
+use crate::lfo::Lfo;
+use crate::oscillator::seeded_random;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Source {
+    Envelope(usize), // one of the matrix's envelopes, from 0 to 1
+    Lfo(usize), // one of the matrix's LFOs, from -1 to 1
+    Velocity, // from 0 to 1, full unless whoever plays the note sets it as the bins' keys have none
+    KeyTracking, // -1 five octaves below note 64, 1 five octaves above
+    ModWheel, // from 0 to 1
+    Aftertouch, // from 0 to 1, stays at 0 unless whoever plays the note changes it while held
+    Random // from 0 to 1, picked when the note starts
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Destination {
+    Pitch, // semitones
+    Amplitude, // fraction of the note's level
+    Cutoff, // octaves
+    Resonance, // fraction of the full range
+    Pan, // -1 is hard left, 1 hard right
+    OscMix, // level of the layers on top of the first oscillator
+    PulseWidth, // fraction of the full range
+    Vowel, // vowels along a, e, i, o, u
+    GrainPosition, // fraction of the source
+    GrainSize, // seconds
+    GrainDensity, // grains per second
+    GrainPitch, // semitones
+    GrainSpray // fraction of the source
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModRoute {
+    pub source: Source,
+    pub destination: Destination,
+    pub amount: f64
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModMatrix {
+    pub envelopes: Vec<Envelope>,
+    pub lfos: Vec<Lfo>,
+    pub routes: Vec<ModRoute>
+}
+
+impl ModMatrix {
+    pub fn route(&mut self, source: Source, destination: Destination, amount: f64) {
+        self.routes.push(ModRoute { source, destination, amount });
+    }
+
+    // triggers are where each of the envelopes picks up from, those that are missing start from silence
+    pub fn source(&self, source: Source, time: f64, n: Note, triggers: &[Trigger], mod_wheel: f64, tempo: f64) -> f64 {
+        match source {
+            Source::Envelope(index) => self.envelopes.get(index).map_or(0_f64, |envelope| {
+                envelope.note_amplitude(time, n.with_trigger(triggers.get(index).copied().unwrap_or_default()))
+            }),
+            Source::Lfo(index) => self.lfos.get(index).map_or(0_f64, |lfo| lfo.value(time, n, tempo)),
+            Source::Velocity => n.velocity,
+            Source::KeyTracking => (n.id - 64) as f64 / 60_f64,
+            Source::ModWheel => mod_wheel,
+            Source::Aftertouch => n.aftertouch,
+            Source::Random => seeded_random(n.seed())
+        }
+    }
+
+    pub fn modulates(&self, destination: Destination) -> bool {
+        self.routes.iter().any(|route| route.destination == destination)
+    }
+
+    // sum of everything routed to destination
+    pub fn value(&self, destination: Destination, time: f64, n: Note, triggers: &[Trigger], mod_wheel: f64, tempo: f64) -> f64 {
+        self.routes.iter()
+            .filter(|route| route.destination == destination)
+            .fold(0_f64, |value, route| value + route.amount * self.source(route.source, time, n, triggers, mod_wheel, tempo))
+    }
+}
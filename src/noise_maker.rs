@@ -1,7 +1,9 @@
 use std::mem::{size_of, MaybeUninit};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread::{self, JoinHandle};
+use sound::dynamics::Dynamics;
+use sound::oscillator::{mono, pan};
 
 mod bindings {
     windows::include_bindings!();
@@ -62,14 +64,6 @@ pub fn enumerate() -> Vec<(usize, String)> {
     devices
 }
 
-fn clip(sample: f64, max: f64) -> f64 {
-    if sample >= 0_f64 {
-        f64::min(sample, max)
-    } else {
-        f64::max(sample, -max)
-    }
-}
-
 pub trait BitDepth {
     fn from_f64(v: f64) -> Self;
 }
@@ -89,20 +83,54 @@ macro_rules! impl_from_f64 {
 
 impl_from_f64!(i8 i16 i32);
 
+// what the user function gives back for every moment in time
+pub trait Frame {
+    fn stereo(self) -> (f64, f64);
+    fn mono(self) -> f64;
+}
+
+// a mono sample sits in the middle of a stereo output
+impl Frame for f64 {
+    fn stereo(self) -> (f64, f64) {
+        pan(self, 0_f64)
+    }
+
+    fn mono(self) -> f64 {
+        self
+    }
+}
+
+impl Frame for (f64, f64) {
+    fn stereo(self) -> (f64, f64) {
+        self
+    }
+
+    fn mono(self) -> f64 {
+        mono(self)
+    }
+}
+
 pub struct NoiseMaker {
     global_time: Arc<Mutex<f64>>,
     ready: Arc<AtomicBool>,
-    thread_handle: JoinHandle<()>
+    thread_handle: JoinHandle<()>,
+    dynamics: Arc<Mutex<Dynamics>>,
+    gain_reduction: Arc<AtomicU64>, // f64 bits, dB
+    clipped: Arc<AtomicUsize> // samples in the last block the clip stage had to change
 }
 
 impl NoiseMaker {
-    pub fn new<T, F>(device_id: usize, sample_rate: u32, channels: u16, blocks: usize, block_samples: u32, user_function: F) -> Self where
+    pub fn new<T, F, O>(device_id: usize, sample_rate: u32, channels: u16, blocks: usize, block_samples: u32, user_function: F) -> Self where
         T: BitDepth + Default + Clone + Send + 'static,
-        F: Fn(f64) -> f64 + Send + 'static {
+        F: Fn(f64) -> O + Send + 'static,
+        O: Frame {
 
         let block_not_zero = Arc::new((Mutex::new(blocks), Condvar::new()));
         let global_time = Arc::new(Mutex::new(0_f64));
         let ready = Arc::new(AtomicBool::new(true));
+        let dynamics = Arc::new(Mutex::new(Dynamics::default()));
+        let gain_reduction = Arc::new(AtomicU64::new(0_f64.to_bits()));
+        let clipped = Arc::new(AtomicUsize::new(0));
         
         let mut wave_format = WAVEFORMATEX {
             wFormatTag: WAVE_FORMAT_PCM as u16,
@@ -140,6 +168,9 @@ impl NoiseMaker {
             let block_not_zero = block_not_zero.clone();
             let global_time = global_time.clone();
             let ready = ready.clone();
+            let dynamics = dynamics.clone();
+            let gain_reduction = gain_reduction.clone();
+            let clipped = clipped.clone();
             move || {
                 let time_step = 1_f64 / 44100_f64;
 
@@ -163,21 +194,46 @@ impl NoiseMaker {
                     }
 
                     let current_block = block_current * block_samples as usize;
+                    let mut clipped_samples = 0;
 
                     for i in (0..block_samples as usize).step_by(channels as usize) {
+                        let global_time_value = {
+                            let global_time = global_time.lock().unwrap();
+                            *global_time
+                        };
+                        // a mono device gets the downmix, so that is what the dynamics have to see
+                        let output = user_function(global_time_value);
+                        let frame = if channels == 1 {
+                            let sample = output.mono();
+                            (sample, sample)
+                        } else {
+                            output.stereo()
+                        };
+                        // compressor and limiter keep the mix in range, the clip is only a safety net
+                        let ((left, right), clip_mode) = {
+                            let mut dynamics = dynamics.lock().unwrap();
+                            let frame = dynamics.process(frame);
+                            gain_reduction.store(dynamics.gain_reduction().to_bits(), Ordering::Relaxed);
+                            (frame, dynamics.clip_mode)
+                        };
+                        let (clipped_left, clipped_right) = (clip_mode.apply(left), clip_mode.apply(right));
+                        clipped_samples += (clipped_left != left) as usize + (channels > 1 && clipped_right != right) as usize;
+                        let (left, right) = (clipped_left, clipped_right);
                         for j in 0..channels as usize {
-                            let global_time_value = {
-                                let global_time = global_time.lock().unwrap();
-                                *global_time
+                            // any channels past the first two get the downmix
+                            let sample = match (channels, j) {
+                                (1, _) | (_, 0) => left,
+                                (_, 1) => right,
+                                _ => mono((left, right))
                             };
-                            let new_sample = T::from_f64(clip(user_function(global_time_value), 1_f64) * max_sample);
-
-                            block_memory[current_block + i + j] = new_sample;
+                            block_memory[current_block + i + j] = T::from_f64(sample * max_sample);
                         }
                         let mut global_time = global_time.lock().unwrap();
                         *global_time += time_step;
                     }
 
+                    clipped.store(clipped_samples, Ordering::Relaxed);
+
                     // send block to sound device
                     unsafe { waveOutPrepareHeader(hw_device, &mut wave_headers[block_current], size_of::<WAVEHDR>() as u32)};
                     unsafe { waveOutWrite(hw_device, &mut wave_headers[block_current], size_of::<WAVEHDR>() as u32)};
@@ -194,10 +250,29 @@ impl NoiseMaker {
         Self {
             global_time,
             ready,
-            thread_handle
+            thread_handle,
+            dynamics,
+            gain_reduction,
+            clipped
         }
     }
 
+    // replaces the compressor and limiter the output goes through
+    pub fn set_dynamics(&self, dynamics: Dynamics) {
+        *self.dynamics.lock().unwrap() = dynamics;
+    }
+
+    // dB the dynamics stage is taking off the output right now
+    pub fn gain_reduction(&self) -> f64 {
+        f64::from_bits(self.gain_reduction.load(Ordering::Relaxed))
+    }
+
+    // samples in the last block sent to the device that the clip stage changed, what
+    // got past the limiter
+    pub fn clipped_samples(&self) -> usize {
+        self.clipped.load(Ordering::Relaxed)
+    }
+
     pub fn get_time(&self) -> f64 {
         let global_time = self.global_time.lock().unwrap();
         *global_time
@@ -0,0 +1,183 @@
+use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_4, PI};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// the rate the stateless oscillators assume when they need to know how long a
+// sample lasts, NoiseMaker steps time at the same rate
+pub const SAMPLE_RATE: f64 = 44100_f64;
+
+pub fn w(hertz: f64) -> f64 {
+    hertz * 2_f64 * PI
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum OscType {
+    SineWave,
+    SquareWave,
+    PulseWave(f64), // square wave spending the given fraction of each cycle high
+    TriangleWave,
+    AnalogSawWave,
+    DigitalSawWave,
+    RandomNoise
+}
+
+pub fn osc(hertz: f64, time: f64, osc_type: OscType, lfo_hertz: f64, lfo_amplitude: f64) -> f64 {
+    let freq = w(hertz) * time + lfo_amplitude * hertz * (w(lfo_hertz) * time).sin();
+
+    match osc_type {
+        OscType::SineWave => freq.sin(),
+        OscType::SquareWave => if freq.sin() > 0_f64 { 1_f64 } else { -1_f64},
+        OscType::PulseWave(width) => if (freq / (2_f64 * PI)).rem_euclid(1_f64) < width.clamp(0.01_f64, 0.99_f64) { 1_f64 } else { -1_f64 },
+        OscType::TriangleWave => freq.sin().asin() * 2_f64 / PI,
+        OscType::AnalogSawWave => (1..100).fold(0_f64, |output, n| output + ((n as f64 * freq).sin() / n as f64)) * 2_f64 / PI,
+        OscType::DigitalSawWave => (2_f64 / PI) * (hertz * PI * (time % (1_f64 / hertz)) - (PI / 2_f64)),
+        OscType::RandomNoise => 2_f64 * random::<f64>() - 1_f64
+    }
+}
+
+// deterministic random number in [0, 1), the same seed always gives the same
+// value so per note randomness survives between samples
+pub fn seeded_random(seed: u64) -> f64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_4d1c_e4e5_b9b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+// constant power pan law, pan goes from -1 (left) to 1 (right)
+pub fn pan(sample: f64, pan: f64) -> (f64, f64) {
+    let angle = (pan.clamp(-1_f64, 1_f64) + 1_f64) * FRAC_PI_4;
+    (sample * angle.cos(), sample * angle.sin())
+}
+
+// folds a stereo pair back to mono, a centred sample comes back at its original level
+pub fn mono((left, right): (f64, f64)) -> f64 {
+    (left + right) * FRAC_1_SQRT_2
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum PanLaw {
+    #[default]
+    ConstantPower, // just as loud wherever it is panned, folds to mono 3 dB quieter at the sides
+    Linear // the sides add up to the sample wherever it is panned, 6 dB down on each side in the middle
+}
+
+impl PanLaw {
+    // neither law gives a side more than the sample itself
+    pub fn pan(&self, sample: f64, pan: f64) -> (f64, f64) {
+        match self {
+            PanLaw::ConstantPower => self::pan(sample, pan),
+            PanLaw::Linear => {
+                let pan = pan.clamp(-1_f64, 1_f64);
+                (sample * (1_f64 - pan) * 0.5_f64, sample * (1_f64 + pan) * 0.5_f64)
+            }
+        }
+    }
+}
+
+// 0 folds everything into the middle, 1 leaves it alone and above 1 pushes it wider ;
+// only the difference between the sides changes, so the mono downmix stays the same
+pub fn width((left, right): (f64, f64), width: f64) -> (f64, f64) {
+    let mid = (left + right) * 0.5_f64;
+    let side = (left - right) * 0.5_f64 * width.max(0_f64);
+    (mid + side, mid - side)
+}
+
+#[derive(Clone, Copy)]
+pub struct Unison {
+    pub voices: u32,
+    pub detune: f64, // cents between the lowest and the highest voice
+    pub spread: f64 // 0 keeps all voices in the centre, 1 puts the outer voices hard left/right
+}
+
+impl Default for Unison {
+    fn default() -> Self {
+        Self {
+            voices: 1,
+            detune: 0_f64,
+            spread: 0_f64
+        }
+    }
+}
+
+impl Unison {
+    // position of a voice in the stack, from -1 to 1
+    fn position(&self, voice: u32) -> f64 {
+        if self.voices <= 1 {
+            0_f64
+        } else {
+            2_f64 * voice as f64 / (self.voices - 1) as f64 - 1_f64
+        }
+    }
+}
+
+// N detuned copies of the same oscillator, each with its own random starting
+// phase, spread across the stereo field ; seed should be unique to the note so
+// the phases stay put for the whole life of the note
+pub fn osc_unison(hertz: f64, time: f64, osc_type: OscType, lfo_hertz: f64, lfo_amplitude: f64, unison: Unison, seed: u64) -> (f64, f64) {
+    let voices = unison.voices.max(1);
+    if voices == 1 {
+        return pan(osc(hertz, time, osc_type, lfo_hertz, lfo_amplitude), 0_f64);
+    }
+
+    let (left, right) = (0..voices).fold((0_f64, 0_f64), |(left, right), voice| {
+        let position = unison.position(voice);
+        let voice_hertz = hertz * 2_f64.powf(position * unison.detune / 2_f64 / 1200_f64);
+        let phase = seeded_random(seed.wrapping_mul(31).wrapping_add(voice as u64)) / voice_hertz;
+        let (l, r) = pan(osc(voice_hertz, time + phase, osc_type, lfo_hertz, lfo_amplitude), position * unison.spread);
+        (left + l, right + r)
+    });
+
+    // uncorrelated voices add up in power, not in amplitude
+    let gain = 1_f64 / (voices as f64).sqrt();
+    (left * gain, right * gain)
+}
+
+#[derive(Clone, Copy)]
+pub enum OscPairMode {
+    HardSync, // slave phase restarts every time the master wraps
+    RingModulation, // master * slave
+    AmplitudeModulation(f64) // slave level follows the master, with the given depth from 0 to 1
+}
+
+// polynomial band-limited step, smooths a unit discontinuity at phase 0 over
+// one sample on either side ; phase and phase_step are in cycles
+fn poly_blep(phase: f64, phase_step: f64) -> f64 {
+    if phase < phase_step {
+        let t = phase / phase_step;
+        t + t - t * t - 1_f64
+    } else if phase > 1_f64 - phase_step {
+        let t = (phase - 1_f64) / phase_step;
+        t * t + t + t + 1_f64
+    } else {
+        0_f64
+    }
+}
+
+// two oscillators working together, in hard sync mode only the slave is heard
+// and its pitch sets the timbre while the master sets the pitch of the note
+pub fn osc_pair(master_hertz: f64, master_type: OscType, slave_hertz: f64, slave_type: OscType, time: f64, mode: OscPairMode) -> f64 {
+    match mode {
+        OscPairMode::HardSync => {
+            if master_hertz <= 0_f64 {
+                return osc(slave_hertz, time, slave_type, 0_f64, 0_f64);
+            }
+
+            let master_period = 1_f64 / master_hertz;
+            let slave_time = time.rem_euclid(master_period);
+            let naive = osc(slave_hertz, slave_time, slave_type, 0_f64, 0_f64);
+
+            // the jump the reset causes, from where the slave was just before the
+            // master wrapped back to where it starts again
+            let jump = osc(slave_hertz, 0_f64, slave_type, 0_f64, 0_f64) - osc(slave_hertz, master_period * (1_f64 - f64::EPSILON), slave_type, 0_f64, 0_f64);
+            naive + jump / 2_f64 * poly_blep(slave_time * master_hertz, master_hertz / SAMPLE_RATE)
+        },
+        OscPairMode::RingModulation =>
+            osc(master_hertz, time, master_type, 0_f64, 0_f64) * osc(slave_hertz, time, slave_type, 0_f64, 0_f64),
+        OscPairMode::AmplitudeModulation(depth) => {
+            let depth = depth.clamp(0_f64, 1_f64);
+            (1_f64 + depth * osc(master_hertz, time, master_type, 0_f64, 0_f64)) / (1_f64 + depth) * osc(slave_hertz, time, slave_type, 0_f64, 0_f64)
+        }
+    }
+}
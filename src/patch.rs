@@ -0,0 +1,438 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use serde::{Deserialize, Serialize};
+use crate::envelope::*;
+use crate::instrument::{scale, Instrument, Moment, Note, ScaleType, Sound};
+use crate::lfo::LfoRate;
+use crate::modulation::*;
+use crate::oscillator::*;
+
+fn invalid(field: &str, message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{} {}", field, message))
+}
+
+fn one() -> f64 {
+    1_f64
+}
+
+fn one_voice() -> u32 {
+    1
+}
+
+// one oscillator of a patch, the layers are added together
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    pub osc_type: OscType,
+    #[serde(default)]
+    pub transpose: i32, // semitones from the note played
+    #[serde(default)]
+    pub key: Option<i32>, // plays this note whatever key is pressed, for drums
+    #[serde(default = "one")]
+    pub level: f64,
+    #[serde(default)]
+    pub lfo_hertz: f64,
+    #[serde(default)]
+    pub lfo_amplitude: f64,
+    #[serde(default = "one_voice")]
+    pub voices: u32, // detuned copies of the oscillator
+    #[serde(default)]
+    pub detune: f64, // cents between the lowest and the highest voice
+    #[serde(default)]
+    pub spread: f64 // 0 keeps all voices in the centre, 1 puts the outer voices hard left/right
+}
+
+impl Layer {
+    pub fn hertz(&self, id: i32) -> f64 {
+        scale(self.key.unwrap_or(id) + self.transpose, ScaleType::Default)
+    }
+
+    pub fn unison(&self) -> Unison {
+        Unison { voices: self.voices, detune: self.detune, spread: self.spread }
+    }
+}
+
+// when the instrument lets go of a note
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum NoteFinished {
+    #[default]
+    Envelope, // once the envelope has released
+    After(f64) // seconds after the note started, for sounds whose envelope never gets there
+}
+
+// an instrument described in a TOML file instead of in code, e.g.
+//
+//     volume = 0.8
+//     [[layers]]
+//     osc_type = "SineWave"
+//     transpose = 12
+//     lfo_hertz = 5
+//     lfo_amplitude = 0.001
+//     voices = 5
+//     detune = 20
+//     spread = 0.5
+//     [envelope.ADSR]
+//     attack_time = 0.01
+//     decay_time = 1
+//     sustain_amplitude = 0
+//     [[matrix.lfos]]
+//     rate = { Hertz = 6 }
+//     [[matrix.routes]]
+//     source = { Lfo = 0 }
+//     destination = "Amplitude"
+//     amount = 0.1
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Patch {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "one")]
+    pub volume: f64,
+    #[serde(default)]
+    pub envelope: Envelope,
+    pub layers: Vec<Layer>, // the first one is left alone by osc mix modulation
+    #[serde(default)]
+    pub note_finished: NoteFinished,
+    #[serde(default)]
+    pub matrix: ModMatrix
+}
+
+impl Patch {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let patch: Patch = toml::from_str(text).map_err(|error| Error::new(ErrorKind::InvalidData, error.to_string()))?;
+        patch.validate()?;
+        Ok(patch)
+    }
+
+    // fails for values TOML has no way of writing down
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|error| Error::new(ErrorKind::InvalidData, error.to_string()))
+    }
+
+    // parses fine but would not make a sensible sound, the error names the field
+    pub fn validate(&self) -> Result<()> {
+        check("volume", self.volume, 0_f64, f64::INFINITY)?;
+        if self.layers.is_empty() {
+            return Err(invalid("layers", "needs at least one oscillator"));
+        }
+        for (i, layer) in self.layers.iter().enumerate() {
+            let field = |name: &str| format!("layers[{}].{}", i, name);
+            if let OscType::PulseWave(width) = layer.osc_type {
+                check(&field("osc_type.PulseWave"), width, 0_f64, 1_f64)?;
+            }
+            if let Some(key) = layer.key {
+                check(&field("key"), key as f64, 0_f64, 127_f64)?;
+            }
+            check(&field("transpose"), layer.transpose as f64, -96_f64, 96_f64)?;
+            check(&field("level"), layer.level, f64::NEG_INFINITY, f64::INFINITY)?;
+            check(&field("lfo_hertz"), layer.lfo_hertz, 0_f64, f64::INFINITY)?;
+            check(&field("lfo_amplitude"), layer.lfo_amplitude, f64::NEG_INFINITY, f64::INFINITY)?;
+            check(&field("voices"), layer.voices as f64, 1_f64, 16_f64)?;
+            check(&field("detune"), layer.detune, 0_f64, 1200_f64)?;
+            check(&field("spread"), layer.spread, 0_f64, 1_f64)?;
+        }
+
+        check_envelope("envelope", &self.envelope)?;
+        for (i, envelope) in self.matrix.envelopes.iter().enumerate() {
+            check_envelope(&format!("matrix.envelopes[{}]", i), envelope)?;
+        }
+        for (i, lfo) in self.matrix.lfos.iter().enumerate() {
+            let field = |name: &str| format!("matrix.lfos[{}].{}", i, name);
+            match lfo.rate {
+                LfoRate::Hertz(hertz) => check(&field("rate.Hertz"), hertz, 0_f64, f64::INFINITY)?,
+                LfoRate::Synced { beats } => check(&field("rate.Synced.beats"), beats, f64::MIN_POSITIVE, f64::INFINITY)?
+            }
+            check(&field("phase"), lfo.phase, 0_f64, 1_f64)?;
+            check(&field("delay"), lfo.delay, 0_f64, f64::INFINITY)?;
+            check(&field("fade_in"), lfo.fade_in, 0_f64, f64::INFINITY)?;
+        }
+        for (i, route) in self.matrix.routes.iter().enumerate() {
+            let field = |name: &str| format!("matrix.routes[{}].{}", i, name);
+            match route.source {
+                Source::Envelope(index) if index >= self.matrix.envelopes.len() =>
+                    return Err(invalid(&field("source.Envelope"), &format!("must be one of the {} matrix envelopes, not {}", self.matrix.envelopes.len(), index))),
+                Source::Lfo(index) if index >= self.matrix.lfos.len() =>
+                    return Err(invalid(&field("source.Lfo"), &format!("must be one of the {} matrix LFOs, not {}", self.matrix.lfos.len(), index))),
+                _ => ()
+            }
+            check(&field("amount"), route.amount, f64::NEG_INFINITY, f64::INFINITY)?;
+        }
+
+        if let NoteFinished::After(seconds) = self.note_finished {
+            if !(seconds > 0_f64 && seconds.is_finite()) {
+                return Err(invalid("note_finished.After", &format!("must be more than 0 seconds, not {}", seconds)));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_envelope(field: &str, envelope: &Envelope) -> Result<()> {
+    match envelope {
+        Envelope::ADSR(adsr) => {
+            check(&format!("{}.ADSR.attack_time", field), adsr.attack_time, 0_f64, f64::INFINITY)?;
+            check(&format!("{}.ADSR.decay_time", field), adsr.decay_time, 0_f64, f64::INFINITY)?;
+            check(&format!("{}.ADSR.release_time", field), adsr.release_time, 0_f64, f64::INFINITY)?;
+            check(&format!("{}.ADSR.sustain_amplitude", field), adsr.sustain_amplitude, 0_f64, f64::INFINITY)?;
+            check(&format!("{}.ADSR.start_amplitude", field), adsr.start_amplitude, 0_f64, f64::INFINITY)?;
+            check_curve(&format!("{}.ADSR.attack_curve", field), adsr.attack_curve)?;
+            check_curve(&format!("{}.ADSR.decay_curve", field), adsr.decay_curve)?;
+            check_curve(&format!("{}.ADSR.release_curve", field), adsr.release_curve)?;
+        },
+        Envelope::Breakpoint(breakpoint) => {
+            let stages = breakpoint.stages.len();
+            if stages == 0 {
+                return Err(invalid(&format!("{}.Breakpoint.stages", field), "needs at least one stage"));
+            }
+            check(&format!("{}.Breakpoint.delay", field), breakpoint.delay, 0_f64, f64::INFINITY)?;
+            for (i, stage) in breakpoint.stages.iter().enumerate() {
+                check(&format!("{}.Breakpoint.stages[{}].time", field, i), stage.time, 0_f64, f64::INFINITY)?;
+                check(&format!("{}.Breakpoint.stages[{}].level", field, i), stage.level, 0_f64, f64::INFINITY)?;
+                check_curve(&format!("{}.Breakpoint.stages[{}].curve", field, i), stage.curve)?;
+            }
+            if let Some(sustain) = breakpoint.sustain {
+                check(&format!("{}.Breakpoint.sustain", field), sustain as f64, 0_f64, (stages - 1) as f64)?;
+            }
+            if let Some((start, end)) = breakpoint.loop_points {
+                check(&format!("{}.Breakpoint.loop_points", field), end as f64, start as f64, (stages - 1) as f64)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_curve(field: &str, curve: Curve) -> Result<()> {
+    match curve {
+        Curve::Tension(tension) => check(&format!("{}.Tension", field), tension, -1_f64, 1_f64),
+        _ => Ok(())
+    }
+}
+
+fn check(field: &str, value: f64, minimum: f64, maximum: f64) -> Result<()> {
+    if !value.is_finite() {
+        Err(invalid(field, &format!("must be a number, not {}", value)))
+    } else if value < minimum || value > maximum {
+        let range = match (minimum.is_finite(), maximum.is_finite()) {
+            (true, true) => format!("between {} and {}", minimum, maximum),
+            (true, false) => format!("at least {}", minimum),
+            _ => format!("at most {}", maximum)
+        };
+        Err(invalid(field, &format!("must be {}, not {}", range, value)))
+    } else {
+        Ok(())
+    }
+}
+
+// a Synth made from a patch starts out with its volume, envelope and matrix
+impl Sound for Patch {
+    type State = ();
+
+    fn state(&self, _n: Note) {}
+
+    fn sample(&self, m: &Moment, _state: &mut ()) -> (f64, f64) {
+        let (left, right) = self.layers.iter().enumerate().fold((0_f64, 0_f64), |(left, right), (i, layer)| {
+            // pulse width modulation moves every pulse layer from where the patch put it
+            let osc_type = match layer.osc_type {
+                OscType::PulseWave(width) => OscType::PulseWave(width + m.pulse_width - 0.5_f64),
+                osc_type => osc_type
+            };
+            let level = if i == 0 { layer.level } else { m.osc_mix * layer.level };
+            let seed = m.note.seed().wrapping_add(i as u64);
+            let (l, r) = osc_unison(layer.hertz(m.note.id), m.life_time, osc_type, layer.lfo_hertz, layer.lfo_amplitude, layer.unison(), seed);
+            (left + level * l, right + level * r)
+        });
+        (m.amplitude * left, m.amplitude * right)
+    }
+
+    fn finished(&self, time: f64, n: Note, envelope: &Envelope, _state: &()) -> bool {
+        match self.note_finished {
+            NoteFinished::Envelope => envelope.finished(time, n),
+            NoteFinished::After(seconds) => time - n.on >= seconds
+        }
+    }
+
+    fn volume(&self) -> f64 {
+        self.volume
+    }
+
+    fn envelope(&self) -> Envelope {
+        self.envelope.clone()
+    }
+
+    fn matrix(&self) -> ModMatrix {
+        self.matrix.clone()
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// keeps an instrument in step with its patch file while playing. The file is checked
+// on a thread of its own and every time it changes the instrument is built again and
+// swapped in whole ; notes hold on to the instrument they started with, so they finish
+// with the old sound while new ones get the new one. A file that doesn't load is read
+// once more on the next check, as editors don't always write it in one go, and if it
+// still fails the last good instrument keeps playing and the error waits to be picked up
+pub struct PatchWatcher {
+    instrument: Arc<Mutex<Arc<dyn Instrument>>>,
+    errors: Arc<Mutex<Vec<Error>>>,
+    running: Arc<AtomicBool>,
+    thread_handle: JoinHandle<()>
+}
+
+impl PatchWatcher {
+    // fails only if the patch can't be loaded the first time
+    pub fn new<P, F, I>(path: P, build: F) -> Result<Self> where
+        P: Into<PathBuf>,
+        F: Fn(Patch) -> I + Send + 'static,
+        I: Instrument + 'static {
+
+        let path = path.into();
+        let mut last_modified = modified(&path);
+        let mut retry = false;
+        let instrument: Arc<Mutex<Arc<dyn Instrument>>> = Arc::new(Mutex::new(Arc::new(build(Patch::load(&path)?))));
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_handle = thread::spawn({
+            let instrument = instrument.clone();
+            let errors = errors.clone();
+            let running = running.clone();
+            move || {
+                while running.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(250));
+                    let current_modified = modified(&path);
+                    if current_modified == last_modified && !retry {
+                        continue;
+                    }
+                    last_modified = current_modified;
+
+                    match Patch::load(&path) {
+                        Ok(patch) => {
+                            retry = false;
+                            *instrument.lock().unwrap() = Arc::new(build(patch));
+                        },
+                        Err(_) if !retry => retry = true,
+                        Err(error) => {
+                            retry = false;
+                            errors.lock().unwrap().push(Error::new(error.kind(), format!("{}: {}", path.display(), error)));
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Self { instrument, errors, running, thread_handle })
+    }
+
+    // what new notes should be played with
+    pub fn instrument(&self) -> Arc<dyn Instrument> {
+        self.instrument.lock().unwrap().clone()
+    }
+
+    // every reload that failed since the last call
+    pub fn take_errors(&self) -> Vec<Error> {
+        std::mem::take(&mut *self.errors.lock().unwrap())
+    }
+
+    pub fn stop(self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.thread_handle.join().expect("Could not join PatchWatcher thread");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINE: &str = "[[layers]]\nosc_type = \"SineWave\"\n";
+
+    fn error(text: &str) -> String {
+        Patch::from_toml(text).err().map(|e| e.to_string()).unwrap_or_default()
+    }
+
+    #[test]
+    fn loads_the_shipped_patches() {
+        for text in [include_str!("../patches/organ.toml"), include_str!("../patches/bell.toml"), include_str!("../patches/tom.toml")] {
+            let patch = Patch::from_toml(text).unwrap();
+            // and writes them back out to the same patch
+            let again = Patch::from_toml(&patch.to_toml().unwrap()).unwrap();
+            assert_eq!(again.name, patch.name);
+            assert_eq!(again.layers.len(), patch.layers.len());
+        }
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        let patch = Patch::from_toml(SINE).unwrap();
+        assert_eq!(patch.volume, 1_f64);
+        assert_eq!(patch.layers[0].level, 1_f64);
+        assert_eq!(patch.layers[0].voices, 1);
+        assert_eq!(patch.note_finished, NoteFinished::Envelope);
+    }
+
+    #[test]
+    fn needs_a_layer() {
+        assert_eq!(error("layers = []"), "layers needs at least one oscillator");
+    }
+
+    #[test]
+    fn names_the_field_out_of_range() {
+        assert_eq!(error(&format!("volume = -1.0\n{}", SINE)), "volume must be at least 0, not -1");
+        assert_eq!(error(&format!("{}transpose = 100\n", SINE)), "layers[0].transpose must be between -96 and 96, not 100");
+        assert_eq!(error(&format!("{}voices = 0\n", SINE)), "layers[0].voices must be between 1 and 16, not 0");
+        assert_eq!(error(&format!("{}spread = 2.0\n", SINE)), "layers[0].spread must be between 0 and 1, not 2");
+        assert_eq!(error("[[layers]]\nosc_type = { PulseWave = 1.5 }\n"), "layers[0].osc_type.PulseWave must be between 0 and 1, not 1.5");
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_numbers() {
+        assert_eq!(error(&format!("{}level = nan\n", SINE)), "layers[0].level must be a number, not NaN");
+        assert_eq!(error(&format!("{}detune = inf\n", SINE)), "layers[0].detune must be a number, not inf");
+    }
+
+    #[test]
+    fn checks_envelopes() {
+        let adsr = "[envelope.ADSR]\nattack_time = 0.1\ndecay_time = 0.1\nrelease_time = 0.1\nsustain_amplitude = 1.0\n";
+        assert!(Patch::from_toml(&format!("{}{}", SINE, adsr)).is_ok());
+        assert_eq!(error(&format!("{}{}", SINE, adsr.replace("decay_time = 0.1", "decay_time = -0.1"))),
+            "envelope.ADSR.decay_time must be at least 0, not -0.1");
+        assert_eq!(error(&format!("{}{}decay_curve = {{ Tension = 2.0 }}\n", SINE, adsr)),
+            "envelope.ADSR.decay_curve.Tension must be between -1 and 1, not 2");
+
+        let breakpoint = "[envelope.Breakpoint]\nstages = [{ time = 0.1, level = 1.0 }, { time = 0.5, level = 0.0 }]\n";
+        assert!(Patch::from_toml(&format!("{}{}", SINE, breakpoint)).is_ok());
+        assert_eq!(error(&format!("{}{}sustain = 2\n", SINE, breakpoint)), "envelope.Breakpoint.sustain must be between 0 and 1, not 2");
+        assert_eq!(error(&format!("{}{}delay = -1.0\n", SINE, breakpoint)), "envelope.Breakpoint.delay must be at least 0, not -1");
+        assert_eq!(error(&format!("{}[envelope.Breakpoint]\nstages = []\n", SINE)), "envelope.Breakpoint.stages needs at least one stage");
+    }
+
+    #[test]
+    fn checks_the_matrix() {
+        let lfo = "[[matrix.lfos]]\nrate = { Hertz = 6.5 }\n";
+        assert!(Patch::from_toml(&format!("{}{}", SINE, lfo)).is_ok());
+        assert_eq!(error(&format!("{}{}phase = 1.5\n", SINE, lfo)), "matrix.lfos[0].phase must be between 0 and 1, not 1.5");
+        assert_eq!(error(&format!("{}{}fade_in = -1.0\n", SINE, lfo)), "matrix.lfos[0].fade_in must be at least 0, not -1");
+        assert!(error(&format!("{}[[matrix.lfos]]\nrate = {{ Synced = {{ beats = 0.0 }} }}\n", SINE)).starts_with("matrix.lfos[0].rate.Synced.beats must be at least"));
+
+        let route = "[[matrix.routes]]\nsource = { Lfo = 1 }\ndestination = \"Pitch\"\namount = 1.0\n";
+        assert_eq!(error(&format!("{}{}{}", SINE, lfo, route)), "matrix.routes[0].source.Lfo must be one of the 1 matrix LFOs, not 1");
+        assert!(Patch::from_toml(&format!("{}{}{}", SINE, lfo, route.replace("Lfo = 1", "Lfo = 0"))).is_ok());
+    }
+
+    #[test]
+    fn needs_a_time_to_finish_after() {
+        assert_eq!(error(&format!("note_finished = {{ After = 0.0 }}\n{}", SINE)), "note_finished.After must be more than 0 seconds, not 0");
+        assert!(Patch::from_toml(&format!("note_finished = {{ After = 2.0 }}\n{}", SINE)).is_ok());
+    }
+}
@@ -0,0 +1,224 @@
+use rand::prelude::*;
+use crate::envelope::Envelope;
+use crate::instrument::*;
+use crate::oscillator::*;
+
+// sound sources that are simulated one sample at a time instead of being
+// computed from the time, held tells the model if the key is still down
+pub trait PhysicalModel: Send {
+    fn tick(&mut self, held: bool) -> f64;
+    fn finished(&self) -> bool;
+}
+
+// how loud the model still is, drops by about 60dB in a third of a second of silence
+fn follow_level(level: f64, output: f64) -> f64 {
+    f64::max(output.abs(), level * 0.9995_f64)
+}
+
+// extended Karplus-Strong : a burst of noise circulating in a delay line one
+// period long, losing a bit of energy and high end on every round trip
+pub struct PluckedString {
+    delay_line: Vec<f64>,
+    position: usize,
+    previous: f64,
+
+    // first order allpass, tunes the fractional part of the period
+    tuning: f64,
+    tuning_input: f64,
+    tuning_output: f64,
+
+    loss: f64,
+    level: f64
+}
+
+impl PluckedString {
+    // damping (0 to 1) sets how fast the string dies out, brightness (0 to 1)
+    // how hard it is plucked and pick_position (0 to 1) where along its length
+    pub fn new(hertz: f64, damping: f64, brightness: f64, pick_position: f64, seed: u64) -> Self {
+        // the averaging loop filter delays by half a sample
+        let period = SAMPLE_RATE / hertz.max(20_f64) - 0.5_f64;
+        let mut length = period.floor();
+        let mut fraction = period - length;
+        // keep the allpass away from a coefficient close to 1, where it rings
+        if fraction < 0.1_f64 {
+            length -= 1_f64;
+            fraction += 1_f64;
+        }
+        let length = length.max(2_f64) as usize;
+
+        // noise burst, darker for lower brightness
+        let smoothing = 0.1_f64 + 0.9_f64 * brightness.clamp(0_f64, 1_f64);
+        let mut previous = 0_f64;
+        let noise: Vec<f64> = (0..length).map(|i| {
+            previous += smoothing * ((2_f64 * seeded_random(seed.wrapping_add(i as u64)) - 1_f64) - previous);
+            previous
+        }).collect();
+
+        // plucking at a point cancels the harmonics that have a node there
+        let pick = (pick_position.clamp(0_f64, 1_f64) * length as f64).round() as usize;
+        let mut delay_line: Vec<f64> = (0..length).map(|i| if pick > 0 && i >= pick { noise[i] - noise[i - pick] } else { noise[i] }).collect();
+
+        let mean = delay_line.iter().sum::<f64>() / length as f64;
+        let peak = delay_line.iter().fold(0_f64, |peak, sample| f64::max(peak, (sample - mean).abs()));
+        delay_line.iter_mut().for_each(|sample| *sample = (*sample - mean) / peak.max(f64::EPSILON));
+
+        Self {
+            delay_line,
+            position: 0,
+            previous: 0_f64,
+
+            tuning: (1_f64 - fraction) / (1_f64 + fraction),
+            tuning_input: 0_f64,
+            tuning_output: 0_f64,
+
+            loss: 0.999_f64 - 0.049_f64 * damping.clamp(0_f64, 1_f64),
+            level: 1_f64
+        }
+    }
+}
+
+impl PhysicalModel for PluckedString {
+    fn tick(&mut self, held: bool) -> f64 {
+        let output = self.delay_line[self.position];
+
+        // releasing the key puts a finger on the string
+        let loss = if held { self.loss } else { self.loss * 0.85_f64 };
+        let filtered = loss * 0.5_f64 * (output + self.previous);
+        self.previous = output;
+
+        let tuned = self.tuning * filtered + self.tuning_input - self.tuning * self.tuning_output;
+        self.tuning_input = filtered;
+        self.tuning_output = tuned;
+
+        self.delay_line[self.position] = tuned;
+        self.position = (self.position + 1) % self.delay_line.len();
+
+        self.level = follow_level(self.level, output);
+        output
+    }
+
+    fn finished(&self) -> bool {
+        self.level < 0.0005_f64
+    }
+}
+
+// single reed blowing into a cylindrical bore, after the STK clarinet
+pub struct BlownPipe {
+    bore: Vec<f64>,
+    bore_delay: f64,
+    write: usize,
+    previous: f64,
+
+    pressure: f64,
+    max_pressure: f64,
+    reed_stiffness: f64,
+    noise: f64,
+
+    time: f64,
+    level: f64
+}
+
+impl BlownPipe {
+    // pressure (0 to 1) is how hard the player blows, reed_stiffness (0 to 1)
+    // how much the reed resists closing and noise (0 to 1) how breathy it sounds
+    pub fn new(hertz: f64, pressure: f64, reed_stiffness: f64, noise: f64) -> Self {
+        // closed at the reed, open at the bell, so the bore is a quarter wave long
+        let bore_delay = (SAMPLE_RATE / hertz.max(20_f64) * 0.5_f64 - 1.5_f64).max(1_f64);
+
+        Self {
+            bore: vec![0_f64; bore_delay.ceil() as usize + 2],
+            bore_delay,
+            write: 0,
+            previous: 0_f64,
+
+            pressure: 0_f64,
+            max_pressure: 0.55_f64 + 0.3_f64 * pressure.clamp(0_f64, 1_f64),
+            reed_stiffness: 0.2_f64 + 0.2_f64 * reed_stiffness.clamp(0_f64, 1_f64),
+            noise: 0.2_f64 * noise.clamp(0_f64, 1_f64),
+
+            time: 0_f64,
+            level: 1_f64
+        }
+    }
+
+    fn bore_output(&self) -> f64 {
+        let length = self.bore.len();
+        let position = self.write as f64 + length as f64 - self.bore_delay;
+        let index = position.floor() as usize;
+        let fraction = position - position.floor();
+        let a = self.bore[index % length];
+        let b = self.bore[(index + 1) % length];
+        a + (b - a) * fraction
+    }
+}
+
+impl PhysicalModel for BlownPipe {
+    fn tick(&mut self, held: bool) -> f64 {
+        // the player takes a moment to build up pressure and to stop blowing
+        if held {
+            self.pressure = f64::min(self.pressure + 0.005_f64, self.max_pressure);
+        } else {
+            self.pressure = f64::max(self.pressure - 0.0005_f64, 0_f64);
+        }
+
+        let vibrato = 0.05_f64 * (w(5.735_f64) * self.time).sin();
+        let breath = self.pressure * (1_f64 + self.noise * (2_f64 * random::<f64>() - 1_f64) + vibrato);
+        self.time += 1_f64 / SAMPLE_RATE;
+
+        let output = self.bore_output();
+
+        // the wave comes back inverted from the open end, losing some high end
+        let reflected = -0.95_f64 * 0.5_f64 * (output + self.previous);
+        self.previous = output;
+
+        let pressure_difference = reflected - breath;
+        let reed = (0.7_f64 - self.reed_stiffness * pressure_difference).clamp(-1_f64, 1_f64);
+        self.bore[self.write] = breath + pressure_difference * reed;
+        self.write = (self.write + 1) % self.bore.len();
+
+        self.level = follow_level(self.level, output);
+        output
+    }
+
+    fn finished(&self) -> bool {
+        self.pressure <= 0_f64 && self.level < 0.0005_f64
+    }
+}
+
+// a plucked string for a Synth, the model shapes the note over time so no envelope is applied
+pub struct Pluck;
+
+impl Sound for Pluck {
+    type State = PluckedString;
+
+    fn state(&self, n: Note) -> PluckedString {
+        PluckedString::new(scale(n.id, ScaleType::Default), 0.1_f64, 0.7_f64, 0.15_f64, n.seed())
+    }
+
+    fn sample(&self, moment: &Moment, string: &mut PluckedString) -> (f64, f64) {
+        pan(string.tick(moment.held()), 0_f64)
+    }
+
+    fn finished(&self, _time: f64, _n: Note, _envelope: &Envelope, string: &PluckedString) -> bool {
+        string.finished()
+    }
+}
+
+// a reed pipe for a Synth, blown for as long as the key is held
+pub struct Pipe;
+
+impl Sound for Pipe {
+    type State = BlownPipe;
+
+    fn state(&self, n: Note) -> BlownPipe {
+        BlownPipe::new(scale(n.id, ScaleType::Default), 0.6_f64, 0.5_f64, 0.5_f64)
+    }
+
+    fn sample(&self, moment: &Moment, pipe: &mut BlownPipe) -> (f64, f64) {
+        pan(pipe.tick(moment.held()), 0_f64)
+    }
+
+    fn finished(&self, _time: f64, _n: Note, _envelope: &Envelope, pipe: &BlownPipe) -> bool {
+        pipe.finished()
+    }
+}
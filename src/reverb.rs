@@ -0,0 +1,245 @@
+use crate::oscillator::SAMPLE_RATE;
+use crate::effect::Effect;
+
+// Jezar's Freeverb tunings at 44.1kHz, the right channel's lines are a little longer
+const COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+// room size stretches every line up to this much
+const MAX_STRETCH: f64 = 1.5_f64;
+pub const MAX_PRE_DELAY: f64 = 0.5_f64;
+
+// a delay line whose buffer is allocated once, big enough for the largest room
+#[derive(Clone)]
+struct Line {
+    buffer: Vec<f64>,
+    length: usize,
+    position: usize
+}
+
+impl Line {
+    fn new(capacity: usize) -> Self {
+        Self { buffer: vec![0_f64; capacity.max(1)], length: capacity.max(1), position: 0 }
+    }
+
+    fn set_length(&mut self, length: usize) {
+        self.length = length.clamp(1, self.buffer.len());
+        self.position %= self.length;
+    }
+
+    fn read(&self) -> f64 {
+        self.buffer[self.position]
+    }
+
+    fn write(&mut self, value: f64) {
+        self.buffer[self.position] = value;
+        self.position = (self.position + 1) % self.length;
+    }
+
+    fn clear(&mut self) {
+        self.buffer.iter_mut().for_each(|sample| *sample = 0_f64);
+    }
+}
+
+// feedback comb with a low pass in the loop
+#[derive(Clone)]
+struct Comb {
+    line: Line,
+    feedback: f64,
+    store: f64
+}
+
+impl Comb {
+    fn process(&mut self, input: f64, damping: f64) -> f64 {
+        let output = self.line.read();
+        self.store = output * (1_f64 - damping) + self.store * damping;
+        self.line.write(input + self.store * self.feedback);
+        output
+    }
+}
+
+#[derive(Clone)]
+struct Allpass {
+    line: Line
+}
+
+impl Allpass {
+    fn process(&mut self, input: f64) -> f64 {
+        let delayed = self.line.read();
+        self.line.write(input + delayed * 0.5_f64);
+        delayed - input
+    }
+}
+
+// Schroeder-Moorer reverb as Freeverb does it: eight parallel combs into four
+// allpasses per side. All the memory it needs is taken when it is made, so it
+// is safe to run inside the render callback
+#[derive(Clone)]
+pub struct Reverb {
+    room_size: f64, // from 0 to 1, stretches the delay lines
+    decay: f64, // seconds for the tail to fall by 60 dB
+    pre_delay: f64, // seconds before the tail starts
+    pub damping: f64, // from 0 to 1, how fast the highs die away
+    pub width: f64, // 0 is mono, 1 full stereo
+    pub mix: f64, // 0 is all dry, 1 all wet, which is what a send wants
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    pre_delay_line: Line
+}
+
+impl Reverb {
+    pub fn new(room_size: f64, decay: f64, mix: f64) -> Self {
+        let scale = SAMPLE_RATE / 44100_f64 * MAX_STRETCH;
+        let side = |spread: usize| {
+            let combs = COMBS.iter()
+                .map(|&length| Comb { line: Line::new(((length + spread) as f64 * scale) as usize), feedback: 0_f64, store: 0_f64 })
+                .collect::<Vec<Comb>>();
+            let allpasses = ALLPASSES.iter()
+                .map(|&length| Allpass { line: Line::new(((length + spread) as f64 * scale) as usize) })
+                .collect::<Vec<Allpass>>();
+            (combs, allpasses)
+        };
+        let (left_combs, left_allpasses) = side(0);
+        let (right_combs, right_allpasses) = side(STEREO_SPREAD);
+
+        let mut reverb = Self {
+            room_size: 0_f64,
+            decay,
+            pre_delay: 0_f64,
+            damping: 0.5_f64,
+            width: 1_f64,
+            mix,
+            combs: [left_combs, right_combs],
+            allpasses: [left_allpasses, right_allpasses],
+            pre_delay_line: Line::new((MAX_PRE_DELAY * SAMPLE_RATE) as usize + 1)
+        };
+        reverb.set_room_size(room_size);
+        reverb.set_pre_delay(0_f64);
+        reverb
+    }
+
+    pub fn with_pre_delay(mut self, pre_delay: f64) -> Self {
+        self.set_pre_delay(pre_delay);
+        self
+    }
+
+    pub fn with_damping(mut self, damping: f64) -> Self {
+        self.damping = damping;
+        self
+    }
+
+    pub fn with_width(mut self, width: f64) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn room_size(&self) -> f64 {
+        self.room_size
+    }
+
+    pub fn decay(&self) -> f64 {
+        self.decay
+    }
+
+    pub fn pre_delay(&self) -> f64 {
+        self.pre_delay
+    }
+
+    pub fn set_room_size(&mut self, room_size: f64) {
+        self.room_size = room_size.clamp(0_f64, 1_f64);
+        // from half the Freeverb room up to its full stretch
+        let stretch = SAMPLE_RATE / 44100_f64 * (0.5_f64 + self.room_size * (MAX_STRETCH - 0.5_f64));
+        for (spread, combs) in [0, STEREO_SPREAD].iter().zip(self.combs.iter_mut()) {
+            for (comb, length) in combs.iter_mut().zip(COMBS.iter()) {
+                comb.line.set_length(((length + spread) as f64 * stretch) as usize);
+            }
+        }
+        for (spread, allpasses) in [0, STEREO_SPREAD].iter().zip(self.allpasses.iter_mut()) {
+            for (allpass, length) in allpasses.iter_mut().zip(ALLPASSES.iter()) {
+                allpass.line.set_length(((length + spread) as f64 * stretch) as usize);
+            }
+        }
+        self.set_decay(self.decay);
+    }
+
+    // every comb loses 60 dB over decay seconds, however long its line
+    pub fn set_decay(&mut self, decay: f64) {
+        self.decay = decay.max(0.01_f64);
+        for comb in self.combs.iter_mut().flatten() {
+            comb.feedback = 10_f64.powf(-3_f64 * comb.line.length as f64 / (self.decay * SAMPLE_RATE)).min(0.98_f64);
+        }
+    }
+
+    pub fn set_pre_delay(&mut self, pre_delay: f64) {
+        self.pre_delay = pre_delay.clamp(0_f64, MAX_PRE_DELAY);
+        self.pre_delay_line.set_length((self.pre_delay * SAMPLE_RATE) as usize + 1);
+    }
+
+    pub fn process(&mut self, (left, right): (f64, f64)) -> (f64, f64) {
+        // both sides share one input, the stereo comes from the different line lengths
+        let input = self.pre_delay_line.read();
+        self.pre_delay_line.write((left + right) * 0.015_f64);
+
+        let damping = self.damping.clamp(0_f64, 1_f64) * 0.4_f64;
+        let mut wet = [0_f64; 2];
+        for (side, output) in wet.iter_mut().enumerate() {
+            let sum = self.combs[side].iter_mut().fold(0_f64, |sum, comb| sum + comb.process(input, damping));
+            *output = self.allpasses[side].iter_mut().fold(sum, |signal, allpass| allpass.process(signal));
+        }
+
+        let width = self.width.clamp(0_f64, 1_f64);
+        let mix = self.mix.clamp(0_f64, 1_f64);
+        let wet1 = 3_f64 * mix * (width / 2_f64 + 0.5_f64);
+        let wet2 = 3_f64 * mix * ((1_f64 - width) / 2_f64);
+        (
+            left * (1_f64 - mix) + wet[0] * wet1 + wet[1] * wet2,
+            right * (1_f64 - mix) + wet[1] * wet1 + wet[0] * wet2
+        )
+    }
+
+    // forgets everything it has heard, ready for a new sound
+    pub fn reset(&mut self) {
+        self.pre_delay_line.clear();
+        for comb in self.combs.iter_mut().flatten() {
+            comb.line.clear();
+            comb.store = 0_f64;
+        }
+        for allpass in self.allpasses.iter_mut().flatten() {
+            allpass.line.clear();
+        }
+    }
+}
+
+impl Effect for Reverb {
+    fn process(&mut self, frame: (f64, f64)) -> (f64, f64) {
+        Reverb::process(self, frame)
+    }
+
+    fn reset(&mut self) {
+        Reverb::reset(self)
+    }
+
+    fn parameters(&self) -> Vec<(String, f64)> {
+        vec![
+            ("room size".to_string(), self.room_size),
+            ("decay".to_string(), self.decay),
+            ("pre delay".to_string(), self.pre_delay),
+            ("damping".to_string(), self.damping),
+            ("width".to_string(), self.width),
+            ("mix".to_string(), self.mix)
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "room size" => self.set_room_size(value),
+            "decay" => self.set_decay(value),
+            "pre delay" => self.set_pre_delay(value),
+            "damping" => self.damping = value,
+            "width" => self.width = value,
+            "mix" => self.mix = value,
+            _ => return false
+        }
+        true
+    }
+}
@@ -0,0 +1,415 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::Arc;
+use crate::envelope::*;
+use crate::instrument::*;
+use crate::oscillator::{pan, SAMPLE_RATE};
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+// a recording loaded in memory, samples go from -1 to 1 and are interleaved
+// when there is more than one channel
+pub struct Sample {
+    pub sample_rate: f64,
+    pub channels: usize,
+    pub data: Vec<f64>,
+    pub root_key: Option<i32>, // unity note from the smpl chunk
+    pub loop_points: Option<(usize, usize)> // first frame and one past the last frame of the loop
+}
+
+impl Sample {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_wav(&fs::read(path)?)
+    }
+
+    // reads 8, 16, 24 and 32 bit PCM and 32 and 64 bit float RIFF WAVE files
+    pub fn from_wav(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(invalid("not a RIFF WAVE file"));
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut root_key = None;
+        let mut loop_points = None;
+
+        let mut offset = 12;
+        while offset + 8 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let size = u32_at(bytes, offset + 4) as usize;
+            let body = &bytes[offset + 8..usize::min(offset + 8 + size, bytes.len())];
+
+            match id {
+                b"fmt " => {
+                    if body.len() < 16 {
+                        return Err(invalid("fmt chunk too short"));
+                    }
+                    let mut tag = u16_at(body, 0);
+                    // WAVE_FORMAT_EXTENSIBLE keeps the real format in its sub format GUID
+                    if tag == 0xfffe && body.len() >= 26 {
+                        tag = u16_at(body, 24);
+                    }
+                    format = Some((tag, u16_at(body, 2) as usize, u32_at(body, 4) as f64, u16_at(body, 14) as usize));
+                },
+                b"data" => data = Some(body),
+                b"smpl" if body.len() >= 36 => {
+                    root_key = Some(u32_at(body, 12) as i32);
+                    if u32_at(body, 28) > 0 && body.len() >= 60 {
+                        // the smpl chunk stores the last frame of the loop, not one past it
+                        loop_points = Some((u32_at(body, 44) as usize, u32_at(body, 48) as usize + 1));
+                    }
+                },
+                _ => ()
+            }
+
+            // chunks are padded to an even size
+            offset += 8 + size + (size & 1);
+        }
+
+        let (tag, channels, sample_rate, bits) = format.ok_or_else(|| invalid("missing fmt chunk"))?;
+        let data = data.ok_or_else(|| invalid("missing data chunk"))?;
+        if channels == 0 {
+            return Err(invalid("no channels"));
+        }
+
+        let data: Vec<f64> = match (tag, bits) {
+            (1, 8) => data.iter().map(|&b| (b as f64 - 128_f64) / 128_f64).collect(),
+            (1, 16) => data.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]]) as f64 / 32768_f64).collect(),
+            (1, 24) => data.chunks_exact(3).map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64 / 8388608_f64).collect(),
+            (1, 32) => data.chunks_exact(4).map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / 2147483648_f64).collect(),
+            (3, 32) => data.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64).collect(),
+            (3, 64) => data.chunks_exact(8).map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])).collect(),
+            _ => return Err(invalid(&format!("unsupported format {} with {} bits per sample", tag, bits)))
+        };
+
+        let frames = data.len() / channels;
+        let loop_points = loop_points.filter(|&(start, end)| start < end && end <= frames);
+
+        Ok(Self {
+            sample_rate,
+            channels,
+            data,
+            root_key,
+            loop_points
+        })
+    }
+
+    // a recording of f over the given number of seconds, for sources that
+    // don't come from a file
+    pub fn render<F: Fn(f64) -> f64>(seconds: f64, f: F) -> Self {
+        Self {
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+            data: (0..(seconds * SAMPLE_RATE) as usize).map(|i| f(i as f64 / SAMPLE_RATE)).collect(),
+            root_key: None,
+            loop_points: None
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.data.len() / self.channels
+    }
+
+    // all channels mixed down, 0 outside of the recording
+    fn frame(&self, index: isize) -> f64 {
+        if index < 0 || index as usize >= self.frames() {
+            return 0_f64;
+        }
+        let start = index as usize * self.channels;
+        self.data[start..start + self.channels].iter().sum::<f64>() / self.channels as f64
+    }
+
+    // value between frames, using a cubic Hermite curve through the 4 closest frames
+    pub fn value(&self, position: f64) -> f64 {
+        let index = position.floor() as isize;
+        let t = position - position.floor();
+        let (y0, y1, y2, y3) = (self.frame(index - 1), self.frame(index), self.frame(index + 1), self.frame(index + 2));
+
+        let c1 = 0.5_f64 * (y2 - y0);
+        let c2 = y0 - 2.5_f64 * y1 + 2_f64 * y2 - 0.5_f64 * y3;
+        let c3 = 0.5_f64 * (y3 - y0) + 1.5_f64 * (y1 - y2);
+        ((c3 * t + c2) * t + c1) * t + y1
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PlayMode {
+    OneShot, // plays to the end whatever the key does
+    LoopedSustain // loops while the key is held, then plays out the rest of the sample
+}
+
+pub struct Zone {
+    pub sample: Arc<Sample>,
+    pub low_key: i32,
+    pub high_key: i32,
+    pub root_key: i32, // the note at which the sample plays at its recorded pitch
+    pub play_mode: PlayMode
+}
+
+pub struct Sampler {
+    pub zones: Vec<Zone>
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        Self {
+            zones: Vec::new()
+        }
+    }
+
+    // one sample over the whole keyboard, rooted at the unity note from its
+    // smpl chunk, or at the given key if it has none
+    pub fn from_file<P: AsRef<Path>>(path: P, root_key: i32, play_mode: PlayMode) -> Result<Self> {
+        let sample = Sample::load(path)?;
+        let mut sampler = Self::new();
+        sampler.add_zone(i32::MIN, i32::MAX, sample.root_key.unwrap_or(root_key), play_mode, Arc::new(sample));
+        Ok(sampler)
+    }
+
+    pub fn add_zone(&mut self, low_key: i32, high_key: i32, root_key: i32, play_mode: PlayMode, sample: Arc<Sample>) {
+        self.zones.push(Zone {
+            sample,
+            low_key,
+            high_key,
+            root_key,
+            play_mode
+        });
+    }
+
+    pub fn zone(&self, note_id: i32) -> Option<&Zone> {
+        self.zones.iter().find(|zone| note_id >= zone.low_key && note_id <= zone.high_key)
+    }
+
+    pub fn play_mode(&self, note_id: i32) -> PlayMode {
+        self.zone(note_id).map_or(PlayMode::OneShot, |zone| zone.play_mode)
+    }
+
+    // what note id sounds like life_time seconds after it started, released of them
+    // since the key was let go ; None once it has played out or if no zone covers it
+    pub fn sample(&self, life_time: f64, released: f64, id: i32) -> Option<f64> {
+        let zone = self.zone(id)?;
+        let sample = &zone.sample;
+        let speed = sample.sample_rate * 2_f64.powf((id - zone.root_key) as f64 / 12_f64);
+
+        if sample.frames() == 0 {
+            return None;
+        }
+
+        let position = match zone.play_mode {
+            PlayMode::OneShot => life_time * speed,
+            PlayMode::LoopedSustain => {
+                // without loop points from the smpl chunk the whole sample loops
+                let (start, end) = sample.loop_points.unwrap_or((0, sample.frames()));
+                let (start, end) = (start as f64, end as f64);
+                let wrap = |position: f64| if position < end { position } else { start + (position - start) % (end - start) };
+                wrap((life_time - released) * speed) + released * speed
+            }
+        };
+
+        if position >= sample.frames() as f64 {
+            None
+        } else {
+            Some(sample.value(position))
+        }
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sampler {
+    // the note as the envelope sees it, one shots play out whatever happens to the key
+    fn envelope_note(&self, n: Note) -> Note {
+        match self.play_mode(n.id) {
+            PlayMode::OneShot => Note { off: f64::NEG_INFINITY, ..n },
+            PlayMode::LoopedSustain => n
+        }
+    }
+}
+
+impl Sound for Sampler {
+    type State = bool; // played to the end
+
+    fn state(&self, _n: Note) -> bool {
+        false
+    }
+
+    fn sample(&self, m: &Moment, played_out: &mut bool) -> (f64, f64) {
+        let released = if m.held() { 0_f64 } else { m.time - m.note.off };
+        match Sampler::sample(self, m.life_time, released, m.note.id) {
+            Some(output) => pan(m.envelope.note_amplitude(m.time, self.envelope_note(m.note)) * output, 0_f64),
+            None => {
+                *played_out = true;
+                (0_f64, 0_f64)
+            }
+        }
+    }
+
+    fn finished(&self, time: f64, n: Note, envelope: &Envelope, played_out: &bool) -> bool {
+        *played_out || envelope.finished(time, self.envelope_note(n))
+    }
+
+    fn envelope(&self) -> Envelope {
+        EnvelopeADSR {
+            attack_time: 0.002_f64,
+            decay_time: 0_f64,
+            release_time: 0.2_f64,
+            sustain_amplitude: 1_f64,
+            ..Default::default()
+        }.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend((body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        if body.len() & 1 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn fmt(tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut body = Vec::new();
+        body.extend(tag.to_le_bytes());
+        body.extend(channels.to_le_bytes());
+        body.extend(sample_rate.to_le_bytes());
+        body.extend((sample_rate * block_align as u32).to_le_bytes());
+        body.extend(block_align.to_le_bytes());
+        body.extend(bits.to_le_bytes());
+        chunk(b"fmt ", &body)
+    }
+
+    fn smpl(root_key: u32, loops: &[(u32, u32)]) -> Vec<u8> {
+        let mut body = vec![0_u8; 36];
+        body[12..16].copy_from_slice(&root_key.to_le_bytes());
+        body[28..32].copy_from_slice(&(loops.len() as u32).to_le_bytes());
+        for &(start, end) in loops {
+            let mut sample_loop = vec![0_u8; 24];
+            sample_loop[8..12].copy_from_slice(&start.to_le_bytes());
+            sample_loop[12..16].copy_from_slice(&end.to_le_bytes());
+            body.extend(sample_loop);
+        }
+        chunk(b"smpl", &body)
+    }
+
+    fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((body.len() as u32 + 4).to_le_bytes());
+        bytes.extend(b"WAVE");
+        bytes.extend(body);
+        bytes
+    }
+
+    fn pcm16(samples: &[i16]) -> Vec<u8> {
+        chunk(b"data", &samples.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<u8>>())
+    }
+
+    #[test]
+    fn reads_16_bit_stereo() {
+        let sample = Sample::from_wav(&wav(&[fmt(1, 2, 48000, 16), pcm16(&[16384, -16384, 0, 32767])])).unwrap();
+        assert_eq!(sample.sample_rate, 48000_f64);
+        assert_eq!(sample.channels, 2);
+        assert_eq!(sample.frames(), 2);
+        assert_eq!(&sample.data[..3], &[0.5_f64, -0.5_f64, 0_f64]);
+        assert_eq!(sample.root_key, None);
+        assert_eq!(sample.loop_points, None);
+    }
+
+    #[test]
+    fn reads_24_bit_and_float() {
+        let data = chunk(b"data", &[0x00, 0x00, 0xc0, 0xff, 0xff, 0x3f]);
+        let sample = Sample::from_wav(&wav(&[fmt(1, 1, 44100, 24), data])).unwrap();
+        assert_eq!(sample.data, vec![-0.5_f64, 0.5_f64 - 1_f64 / 8388608_f64]);
+
+        let data = chunk(b"data", &[0.25_f32.to_le_bytes(), (-1_f32).to_le_bytes()].concat());
+        let sample = Sample::from_wav(&wav(&[fmt(3, 1, 44100, 32), data])).unwrap();
+        assert_eq!(sample.data, vec![0.25_f64, -1_f64]);
+    }
+
+    #[test]
+    fn reads_extensible_format_from_its_sub_format() {
+        let mut format = fmt(0xfffe, 1, 44100, 16);
+        let mut extension = vec![0_u8; 24];
+        extension[8..10].copy_from_slice(&1_u16.to_le_bytes());
+        format.extend(extension);
+        format[4..8].copy_from_slice(&40_u32.to_le_bytes());
+        let sample = Sample::from_wav(&wav(&[format, pcm16(&[-32768])])).unwrap();
+        assert_eq!(sample.data, vec![-1_f64]);
+    }
+
+    #[test]
+    fn skips_padded_chunks_it_does_not_know() {
+        let sample = Sample::from_wav(&wav(&[fmt(1, 1, 44100, 16), chunk(b"LIST", b"odd"), pcm16(&[8192])])).unwrap();
+        assert_eq!(sample.data, vec![0.25_f64]);
+    }
+
+    #[test]
+    fn reads_root_key_and_loop_from_smpl() {
+        let sample = Sample::from_wav(&wav(&[fmt(1, 1, 44100, 16), pcm16(&[0; 100]), smpl(60, &[(10, 89)])])).unwrap();
+        assert_eq!(sample.root_key, Some(60));
+        // the smpl chunk's end is the last frame of the loop
+        assert_eq!(sample.loop_points, Some((10, 90)));
+    }
+
+    #[test]
+    fn drops_loops_outside_the_data() {
+        let sample = Sample::from_wav(&wav(&[fmt(1, 1, 44100, 16), pcm16(&[0; 100]), smpl(60, &[(10, 100)])])).unwrap();
+        assert_eq!(sample.root_key, Some(60));
+        assert_eq!(sample.loop_points, None);
+
+        let sample = Sample::from_wav(&wav(&[fmt(1, 1, 44100, 16), pcm16(&[0; 100]), smpl(60, &[(50, 20)])])).unwrap();
+        assert_eq!(sample.loop_points, None);
+
+        let sample = Sample::from_wav(&wav(&[fmt(1, 1, 44100, 16), pcm16(&[0; 100]), smpl(72, &[])])).unwrap();
+        assert_eq!(sample.root_key, Some(72));
+        assert_eq!(sample.loop_points, None);
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_play() {
+        let error = |bytes: &[u8]| Sample::from_wav(bytes).err().map(|e| e.to_string());
+        assert_eq!(error(b"RIFX\0\0\0\0WAVE"), Some("not a RIFF WAVE file".to_string()));
+        assert_eq!(error(&wav(&[pcm16(&[0])])), Some("missing fmt chunk".to_string()));
+        assert_eq!(error(&wav(&[fmt(1, 1, 44100, 16)])), Some("missing data chunk".to_string()));
+        assert_eq!(error(&wav(&[fmt(1, 0, 44100, 16), pcm16(&[0])])), Some("no channels".to_string()));
+        assert_eq!(error(&wav(&[fmt(2, 1, 44100, 4), pcm16(&[0])])), Some("unsupported format 2 with 4 bits per sample".to_string()));
+    }
+
+    #[test]
+    fn loops_while_held_and_plays_on_once_released() {
+        let mut sample = Sample::render(0.01_f64, |t| t);
+        sample.loop_points = Some((100, 200));
+        let mut sampler = Sampler::new();
+        sampler.add_zone(0, 127, 64, PlayMode::LoopedSustain, Arc::new(sample));
+        let frame = |life_time: f64, released: f64| sampler.sample(life_time, released, 64).unwrap() * SAMPLE_RATE;
+
+        // 250 frames in is 50 frames into the second time round the loop
+        assert!((frame(250_f64 / SAMPLE_RATE, 0_f64) - 150_f64).abs() < 1e-9);
+        // released after 250 frames and 20 more since then
+        assert!((frame(270_f64 / SAMPLE_RATE, 20_f64 / SAMPLE_RATE) - 170_f64).abs() < 1e-9);
+        // and stops at the end of the recording
+        assert_eq!(sampler.sample(550_f64 / SAMPLE_RATE, 400_f64 / SAMPLE_RATE, 64), None);
+    }
+}